@@ -1,5 +1,54 @@
 import zmq
 import struct
+from enum import IntEnum
+
+
+class ErrorCode(IntEnum):
+    """Stable error codes sent in the third frame of an ERR reply."""
+
+    UNKNOWN = 0
+    EMPTY_MESSAGE = 1
+    BAD_FRAME_COUNT = 2
+    BAD_TIMESTAMP = 3
+    KEY_NOT_UTF8 = 4
+    UNKNOWN_COMMAND = 5
+    STORE_ERROR = 6
+    OVER_LIMIT = 7
+    KEY_TOO_LARGE = 8
+    VALUE_TOO_LARGE = 9
+    MESSAGE_TOO_LARGE = 10
+    NO_SESSION = 11
+    BAD_ARGUMENT = 12
+    DENIED = 13
+    NO_NAMESPACE = 14
+    QUOTA_EXCEEDED = 15
+    THROTTLED = 16
+
+
+class KVZError(RuntimeError):
+    """ERR reply from the server; `code` is an ErrorCode. `retry_after` is the
+    server's suggested delay in seconds for THROTTLED errors, else None."""
+
+    def __init__(self, op: str, code: ErrorCode, msg: str, retry_after=None):
+        super().__init__(f"{op} ERR {code.name}: {msg}")
+        self.code = code
+        self.msg = msg
+        self.retry_after = retry_after
+
+    @classmethod
+    def from_reply(cls, op: str, rep):
+        msg = rep[1].decode("utf-8", errors="ignore") if len(rep) > 1 else ""
+        code = ErrorCode.UNKNOWN
+        if len(rep) > 2 and len(rep[2]) == 2:
+            raw = struct.unpack(">H", rep[2])[0]
+            try:
+                code = ErrorCode(raw)
+            except ValueError:
+                pass
+        retry_after = None
+        if len(rep) > 3 and len(rep[3]) == 8:
+            retry_after = struct.unpack(">Q", rep[3])[0] / 1000.0
+        return cls(op, code, msg, retry_after)
 
 
 class KVZClient:
@@ -8,17 +57,42 @@ class KVZClient:
     Protocol:
       PUT: ["PUT", key, ts(8B BE), data]
       GET: ["GET", key]
+      LIMITS: ["LIMITS"]
+      GETRANGE: ["GETRANGE", key, offset(8B BE), len(8B BE)]
+      Any request can be prefixed with ["NS", namespace].
+    Errors come back as ["ERR", msg, code(2B BE)] and are raised as KVZError.
     """
 
-    def __init__(self, connect="tcp://localhost:5555"):
+    def __init__(self, connect="tcp://localhost:5555", curve_server_key=None, namespace=None):
+        """curve_server_key: the server's Z85 public key (str or bytes) to
+        enable CURVE; the client uses an ephemeral keypair.
+        namespace: send every request to this namespace instead of "default"."""
+        self.namespace = namespace.encode("utf-8") if isinstance(namespace, str) else namespace
         ctx = zmq.Context()
         self.sock = ctx.socket(zmq.REQ)
+        if curve_server_key is not None:
+            if isinstance(curve_server_key, str):
+                curve_server_key = curve_server_key.encode("ascii")
+            public, secret = zmq.curve_keypair()
+            self.sock.curve_serverkey = curve_server_key
+            self.sock.curve_publickey = public
+            self.sock.curve_secretkey = secret
         self.sock.connect(connect)
 
-    def put(self, key: str, ts: int, data: bytes) -> str:
-        """Store value, returns "OK", "STALE", or raises Exception."""
+    def _send(self, frames):
+        if self.namespace is not None:
+            frames = [b"NS", self.namespace] + frames
+        self.sock.send_multipart(frames)
+
+    @staticmethod
+    def _key(key) -> bytes:
+        """Keys are raw bytes; str keys are sent as UTF-8."""
+        return key if isinstance(key, (bytes, bytearray)) else key.encode("utf-8")
+
+    def put(self, key, ts: int, data: bytes) -> str:
+        """Store value, returns "OK", "STALE", or raises KVZError."""
         ts_bytes = struct.pack(">Q", ts)  # 8 bytes big-endian
-        self.sock.send_multipart([b"PUT", key.encode("utf-8"), ts_bytes, data])
+        self._send([b"PUT", self._key(key), ts_bytes, data])
         rep = self.sock.recv_multipart()
         if not rep:
             raise RuntimeError("empty reply")
@@ -26,16 +100,15 @@ class KVZClient:
         if code in ("OK", "STALE"):
             return code
         if code == "ERR":
-            msg = rep[1].decode("utf-8", errors="ignore") if len(rep) > 1 else ""
-            raise RuntimeError(f"PUT ERR: {msg}")
+            raise KVZError.from_reply("PUT", rep)
         raise RuntimeError(f"Unexpected reply: {rep}")
 
-    def get(self, key: str):
+    def get(self, key):
         """
         Fetch value. Returns (timestamp:int, data:bytes) or None if not found.
-        Raises Exception on error.
+        Raises KVZError on error.
         """
-        self.sock.send_multipart([b"GET", key.encode("utf-8")])
+        self._send([b"GET", self._key(key)])
         rep = self.sock.recv_multipart()
         if not rep:
             raise RuntimeError("empty reply")
@@ -49,8 +122,63 @@ class KVZClient:
             data = rep[2]
             return ts, data
         if code == "ERR":
-            msg = rep[1].decode("utf-8", errors="ignore") if len(rep) > 1 else ""
-            raise RuntimeError(f"GET ERR: {msg}")
+            raise KVZError.from_reply("GET", rep)
+        raise RuntimeError(f"Unexpected reply: {rep}")
+
+    def get_range(self, key, offset: int, length: int):
+        """
+        Fetch part of a value. Returns (timestamp, total_len, data) or None if
+        not found; the range is clamped to the value so data may be short.
+        """
+        self._send(
+            [b"GETRANGE", self._key(key), struct.pack(">Q", offset), struct.pack(">Q", length)]
+        )
+        rep = self.sock.recv_multipart()
+        if not rep:
+            raise RuntimeError("empty reply")
+        code = rep[0].decode("utf-8", errors="ignore")
+        if code == "MISS":
+            return None
+        if code == "OK":
+            if len(rep) != 4:
+                raise RuntimeError("malformed GETRANGE reply")
+            ts = struct.unpack(">Q", rep[1])[0]
+            total = struct.unpack(">Q", rep[2])[0]
+            return ts, total, rep[3]
+        if code == "ERR":
+            raise KVZError.from_reply("GETRANGE", rep)
+        raise RuntimeError(f"Unexpected reply: {rep}")
+
+    def limits(self) -> dict:
+        """Server size limits: max_key_bytes, max_value_bytes, max_msg_bytes."""
+        self._send([b"LIMITS"])
+        rep = self.sock.recv_multipart()
+        if not rep:
+            raise RuntimeError("empty reply")
+        code = rep[0].decode("utf-8", errors="ignore")
+        if code == "OK":
+            if len(rep) != 4:
+                raise RuntimeError("malformed LIMITS reply")
+            key, value, msg = (struct.unpack(">Q", f)[0] for f in rep[1:])
+            return {"max_key_bytes": key, "max_value_bytes": value, "max_msg_bytes": msg}
+        if code == "ERR":
+            raise KVZError.from_reply("LIMITS", rep)
+        raise RuntimeError(f"Unexpected reply: {rep}")
+
+    def info(self) -> dict:
+        """Server status as a dict of strings (lists are space-separated)."""
+        self._send([b"INFO"])
+        rep = self.sock.recv_multipart()
+        if not rep:
+            raise RuntimeError("empty reply")
+        code = rep[0].decode("utf-8", errors="ignore")
+        if code == "OK":
+            fields = [f.decode("utf-8", errors="replace") for f in rep[1:]]
+            if len(fields) % 2:
+                raise RuntimeError("malformed INFO reply")
+            return dict(zip(fields[::2], fields[1::2]))
+        if code == "ERR":
+            raise KVZError.from_reply("INFO", rep)
         raise RuntimeError(f"Unexpected reply: {rep}")
 
 
@@ -0,0 +1,139 @@
+//! Per-identity read/write permissions on key prefixes.
+//!
+//! With `--acl FILE`, every key access is checked against the rules for the
+//! request's identity (see [`crate::auth`]) before the store is touched;
+//! anything not granted is refused with `DENIED`. Without `--acl` all
+//! identities may read and write everything.
+//!
+//! Rule format, one per line (`#` starts a comment line):
+//!
+//! ```text
+//! <identity|*> <r|w|rw> [<namespace|*>] <prefix|*>
+//! ```
+//!
+//! `*` as identity matches every client (including `anonymous`), `*` as
+//! prefix matches every key. Prefixes are literal UTF-8 text, or raw bytes
+//! written as `hex:<hex>`.
+//!
+//! A rule without a namespace column applies to that prefix in **every**
+//! namespace, including ones created later by someone else; name the
+//! namespace to confine it. Commands that are not about one key (namespace
+//! administration, INFO, SLOWLOG, HOTKEYS) need a rule on all keys in all
+//! namespaces.
+
+use crate::protocol::{ErrorCode, KvzError};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Args;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+struct Rule {
+    /// None matches every identity.
+    identity: Option<String>,
+    read: bool,
+    write: bool,
+    /// None matches every namespace.
+    namespace: Option<String>,
+    prefix: Vec<u8>,
+}
+
+pub struct Acl {
+    rules: Vec<Rule>,
+}
+
+impl Acl {
+    pub fn parse(text: &str) -> Result<Acl> {
+        let mut rules = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            rules.push(parse_rule(line).with_context(|| format!("line {}", n + 1))?);
+        }
+        Ok(Acl { rules })
+    }
+
+    pub fn load(path: &Path) -> Result<Acl> {
+        let text = fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+        Acl::parse(&text).with_context(|| format!("parse {}", path.display()))
+    }
+
+    /// Whether `identity` may access `key` in namespace `ns`; `ns` None asks
+    /// for every namespace at once.
+    pub fn allows(&self, identity: &str, access: Access, ns: Option<&str>, key: &[u8]) -> bool {
+        self.rules.iter().any(|r| {
+            r.identity.as_deref().is_none_or(|i| i == identity)
+                && r.namespace.as_deref().is_none_or(|n| ns == Some(n))
+                && match access {
+                    Access::Read => r.read,
+                    Access::Write => r.write,
+                }
+                && key.starts_with(&r.prefix)
+        })
+    }
+
+    /// [`Acl::allows`] as a protocol error.
+    pub fn check(&self, identity: &str, access: Access, ns: Option<&str>, key: &[u8]) -> Result<(), KvzError> {
+        if self.allows(identity, access, ns, key) {
+            return Ok(());
+        }
+        let what = match access {
+            Access::Read => "read",
+            Access::Write => "write",
+        };
+        Err(KvzError::new(ErrorCode::Denied, format!("{identity} may not {what} this key")))
+    }
+}
+
+fn parse_rule(line: &str) -> Result<Rule> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (identity, perms, namespace, prefix) = match words[..] {
+        [identity, perms, prefix] => (identity, perms, "*", prefix),
+        [identity, perms, namespace, prefix] => (identity, perms, namespace, prefix),
+        _ => bail!("expected `<identity> <r|w|rw> [<namespace>] <prefix>`"),
+    };
+    let (read, write) = match perms {
+        "r" => (true, false),
+        "w" => (false, true),
+        "rw" => (true, true),
+        _ => bail!("unknown permission {perms:?} (expected r, w or rw)"),
+    };
+    let prefix = match prefix {
+        "*" => Vec::new(),
+        p => match p.strip_prefix("hex:") {
+            Some(h) => hex::decode(h).map_err(|e| anyhow!("invalid hex prefix: {e}"))?,
+            None => p.as_bytes().to_vec(),
+        },
+    };
+    let identity = (identity != "*").then(|| identity.to_string());
+    let namespace = (namespace != "*").then(|| namespace.to_string());
+    Ok(Rule { identity, read, write, namespace, prefix })
+}
+
+/// `--acl`.
+#[derive(Args, Debug, Clone, Default)]
+pub struct AclArgs {
+    /// Key-prefix permissions per client identity (see `kvz::acl`); rules
+    /// without a namespace apply in every namespace; default: allow all
+    #[arg(long, value_name = "FILE")]
+    pub acl: Option<PathBuf>,
+}
+
+impl AclArgs {
+    pub fn load(&self) -> Result<Option<Acl>> {
+        let Some(path) = &self.acl else {
+            return Ok(None);
+        };
+        let acl = Acl::load(path)?;
+        info!(path = %path.display(), rules = acl.rules.len(), "acl loaded");
+        Ok(Some(acl))
+    }
+}
@@ -0,0 +1,262 @@
+//! ZAP authentication: client allowlists and per-request identities.
+//!
+//! With `--allowlist FILE` the servers run a ZAP handler (RFC 27) that checks
+//! every new connection against the file and hands libzmq the client's
+//! identity, which then arrives as the `User-Id` property of each request.
+//! The file is re-read whenever its modification time changes.
+//!
+//! Allowlist format, one entry per line (`#` starts a comment line):
+//!
+//! ```text
+//! key <Z85 CURVE public key> [identity]
+//! ip  <address>[/prefix]     [identity]
+//! ```
+//!
+//! A connection is allowed if its CURVE key or its source IP matches an entry;
+//! key entries are checked first. The identity defaults to `key:<Z85>` or
+//! `ip:<address>`. Connections without CURVE can only match `ip` entries.
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Args;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::SystemTime;
+use tracing::{error, info, warn};
+
+/// Identity of requests that did not go through ZAP.
+pub const ANONYMOUS: &str = "anonymous";
+
+/// ZAP domain of endpoints using `--allowlist`; libzmq only consults the
+/// handler when one is set. Endpoints with their own allowlist use `kvz.<n>`.
+pub const ZAP_DOMAIN: &str = "kvz";
+/// ZAP domain of endpoints without an allowlist on a socket where others
+/// have one (libzmq cannot clear a domain once set); always accepted.
+pub const OPEN_ZAP_DOMAIN: &str = "kvz.open";
+/// Well-known endpoint libzmq sends ZAP requests to.
+const ZAP_ENDPOINT: &str = "inproc://zeromq.zap.01";
+
+struct IpRule {
+    addr: u128,
+    mask: u128,
+    v4: bool,
+}
+
+impl IpRule {
+    fn parse(s: &str) -> Result<IpRule> {
+        let (addr, bits) = match s.split_once('/') {
+            Some((a, b)) => (a, Some(b.parse::<u32>().with_context(|| format!("bad prefix length in {s:?}"))?)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().with_context(|| format!("bad IP address {addr:?}"))?;
+        let (v4, width) = match addr {
+            IpAddr::V4(_) => (true, 32),
+            IpAddr::V6(_) => (false, 128),
+        };
+        let bits = bits.unwrap_or(width);
+        if bits > width {
+            bail!("prefix length {bits} is too long for {addr}");
+        }
+        let mask = if bits == 0 { 0 } else { u128::MAX << (128 - bits) };
+        Ok(IpRule { addr: ip_bits(addr) & mask, mask, v4 })
+    }
+
+    fn matches(&self, addr: IpAddr) -> bool {
+        let addr = match addr {
+            IpAddr::V6(a) => a.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(addr),
+            a => a,
+        };
+        self.v4 == addr.is_ipv4() && ip_bits(addr) & self.mask == self.addr
+    }
+}
+
+/// Address bits left-aligned in a u128, so one mask works for both families.
+fn ip_bits(addr: IpAddr) -> u128 {
+    match addr {
+        IpAddr::V4(a) => (u32::from(a) as u128) << 96,
+        IpAddr::V6(a) => u128::from(a),
+    }
+}
+
+#[derive(Default)]
+pub struct Allowlist {
+    keys: Vec<(Vec<u8>, String)>,
+    ips: Vec<(IpRule, String)>,
+}
+
+impl Allowlist {
+    pub fn parse(text: &str) -> Result<Allowlist> {
+        let mut list = Allowlist::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            list.add(line).with_context(|| format!("line {}", n + 1))?;
+        }
+        Ok(list)
+    }
+
+    fn add(&mut self, line: &str) -> Result<()> {
+        let mut words = line.split_whitespace();
+        let kind = words.next().unwrap_or_default();
+        let value = words.next().ok_or_else(|| anyhow!("missing value"))?;
+        let name = words.next().map(str::to_string);
+        if words.next().is_some() {
+            bail!("too many fields");
+        }
+        match kind {
+            "key" => {
+                let key = zmq::z85_decode(value).map_err(|e| anyhow!("invalid Z85 key: {e}"))?;
+                if key.len() != 32 {
+                    bail!("CURVE keys are 40 Z85 characters");
+                }
+                let name = name.unwrap_or_else(|| format!("key:{value}"));
+                self.keys.push((key, name));
+            }
+            "ip" => {
+                let name = name.unwrap_or_else(|| format!("ip:{value}"));
+                self.ips.push((IpRule::parse(value)?, name));
+            }
+            _ => bail!("unknown entry type {kind:?} (expected key or ip)"),
+        }
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Allowlist> {
+        let text = fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+        Allowlist::parse(&text).with_context(|| format!("parse {}", path.display()))
+    }
+
+    /// Identity for a client, or None if it is not allowed.
+    pub fn check(&self, curve_key: Option<&[u8]>, address: &str) -> Option<&str> {
+        if let Some(key) = curve_key
+            && let Some((_, name)) = self.keys.iter().find(|(k, _)| k == key)
+        {
+            return Some(name);
+        }
+        let addr: IpAddr = address.parse().ok()?;
+        self.ips.iter().find(|(r, _)| r.matches(addr)).map(|(_, name)| name.as_str())
+    }
+
+    fn len(&self) -> usize {
+        self.keys.len() + self.ips.len()
+    }
+}
+
+/// An allowlist that follows its file.
+struct WatchedAllowlist {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    list: Allowlist,
+}
+
+impl WatchedAllowlist {
+    fn open(path: PathBuf) -> Result<WatchedAllowlist> {
+        let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+        let list = Allowlist::load(&path)?;
+        Ok(WatchedAllowlist { path, modified, list })
+    }
+
+    /// Reload if the file changed. A file that fails to parse keeps the
+    /// previous list in force.
+    fn refresh(&mut self) -> &Allowlist {
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        if modified != self.modified {
+            self.modified = modified;
+            match Allowlist::load(&self.path) {
+                Ok(list) => {
+                    info!(path = %self.path.display(), entries = list.len(), "allowlist reloaded");
+                    self.list = list;
+                }
+                Err(e) => warn!(error = %format!("{e:#}"), "keeping previous allowlist"),
+            }
+        }
+        &self.list
+    }
+}
+
+/// Answer ZAP requests until the context is terminated, checking each
+/// against the allowlist of its ZAP domain.
+fn zap_loop(sock: zmq::Socket, mut allowlists: Vec<(String, WatchedAllowlist)>) -> Result<()> {
+    loop {
+        let req = sock.recv_multipart(0)?;
+        // version, request id, domain, address, routing id, mechanism, credentials...
+        if req.len() < 6 {
+            // REP must answer every request; libzmq never sends these.
+            sock.send_multipart([b"1.0".as_slice(), b"", b"500", b"malformed ZAP request", b"", b""], 0)?;
+            continue;
+        }
+        let (version, request_id) = (&req[0], &req[1]);
+        let domain = String::from_utf8_lossy(&req[2]);
+        let address = String::from_utf8_lossy(&req[3]);
+        let mechanism = String::from_utf8_lossy(&req[5]);
+        let curve_key = match (&*mechanism, req.get(6)) {
+            ("CURVE", Some(k)) => Some(k.as_slice()),
+            _ => None,
+        };
+        // libzmq also asks about CURVE handshakes on endpoints without a
+        // domain, which have no allowlist.
+        let open = domain.is_empty() || domain == OPEN_ZAP_DOMAIN;
+        let allowlist = allowlists.iter_mut().find(|(d, _)| *d == domain).map(|(_, list)| list.refresh());
+        let (status, text, user) = match allowlist.map(|list| list.check(curve_key, &address)) {
+            None if open => ("200", "OK", String::new()),
+            Some(Some(name)) => ("200", "OK", name.to_string()),
+            _ => {
+                let key = curve_key.and_then(|k| zmq::z85_encode(k).ok());
+                warn!(
+                    domain = %domain,
+                    mechanism = %mechanism,
+                    address = %if address.is_empty() { "local peer" } else { &address },
+                    key = key.as_deref().unwrap_or("none"),
+                    "connection denied"
+                );
+                ("400", "not on allowlist", String::new())
+            }
+        };
+        sock.send_multipart(
+            [version.as_slice(), request_id, status.as_bytes(), text.as_bytes(), user.as_bytes(), b""],
+            0,
+        )?;
+    }
+}
+
+/// `--allowlist`.
+#[derive(Args, Debug, Clone, Default)]
+pub struct AuthArgs {
+    /// Only accept clients listed in FILE (CURVE keys or source IPs; reloaded on change)
+    #[arg(long, value_name = "FILE")]
+    pub allowlist: Option<PathBuf>,
+}
+
+/// Start the ZAP handler on `ctx` for `(domain, allowlist file)` pairs.
+/// Endpoints bound with one of the domains (`set_zap_domain` before bind)
+/// only accept clients on its list. Does nothing for an empty list.
+pub fn start_zap(ctx: &zmq::Context, allowlists: Vec<(String, PathBuf)>) -> Result<()> {
+    if allowlists.is_empty() {
+        return Ok(());
+    }
+    let mut lists = Vec::with_capacity(allowlists.len());
+    for (domain, path) in allowlists {
+        let allowlist = WatchedAllowlist::open(path.clone())?;
+        info!(path = %path.display(), domain = %domain, entries = allowlist.list.len(), "allowlist loaded");
+        lists.push((domain, allowlist));
+    }
+    let handler = ctx.socket(zmq::REP)?;
+    handler.bind(ZAP_ENDPOINT).context("bind ZAP handler")?;
+    thread::spawn(move || {
+        if let Err(e) = zap_loop(handler, lists) {
+            error!(error = %e, "ZAP handler stopped");
+        }
+    });
+    Ok(())
+}
+
+/// Identity the ZAP handler attached to a received frame, if any.
+pub fn frame_user_id(frame: &mut zmq::Message) -> Option<String> {
+    match frame.gets("User-Id") {
+        Some(id) if !id.is_empty() => Some(id.to_string()),
+        _ => None,
+    }
+}
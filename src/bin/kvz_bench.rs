@@ -1,14 +1,31 @@
 use anyhow::{anyhow, Context, Result};
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use kvz::curve::CurveClientArgs;
+use kvz::histogram::Histogram;
+use kvz::logging::{utc_time, LogArgs};
+use kvz::protocol::parse_err;
 use rand::{distributions::Alphanumeric, rngs::StdRng, Rng, SeedableRng};
-// use std::cmp::Ordering;
+use serde_json::{json, Map, Value};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Sender};
 use std::sync::{Arc, Barrier};
 use std::thread;
 use std::time::{Duration, Instant};
+use tracing::error;
+
+/// A step that reaches less than this fraction of its target rate is saturated.
+const SATURATION: f64 = 0.95;
+/// Version of the `--output json` layout.
+const REPORT_VERSION: u64 = 1;
+/// Latency fields `compare` checks; max and p99.99 are too noisy to gate on.
+const COMPARED: [&str; 5] = ["p50", "p95", "p99", "p99.9", "avg"];
 
 #[derive(Parser, Debug, Clone)]
-#[command(about = "Latency benchmark for kvz ZeroMQ K/V store")]
+#[command(about = "Latency benchmark for kvz ZeroMQ K/V store", args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// Endpoint to connect to (REQ)
     #[arg(long, default_value = "tcp://127.0.0.1:5555")]
     connect: String,
@@ -21,217 +38,1202 @@ struct Args {
     /// Fraction of GET ops (0.0..=1.0). e.g. 0.9 = 90% GET, 10% PUT
     #[arg(long, default_value_t = 0.9)]
     get_ratio: f64,
-    /// Bytes of value payload
+    /// Bytes of value payload (fixed size; lognormal median)
     #[arg(long, default_value_t = 256)]
     value_size: usize,
+    /// Distribution of value sizes
+    #[arg(long, value_enum, default_value_t = ValueDist::Fixed)]
+    value_dist: ValueDist,
+    /// Smallest value for uniform and lognormal sizes
+    #[arg(long, value_name = "BYTES", default_value_t = 1)]
+    value_min: usize,
+    /// Largest value for uniform and lognormal sizes
+    #[arg(long, value_name = "BYTES", default_value_t = 1 << 20)]
+    value_max: usize,
+    /// Lognormal: standard deviation of ln(size)
+    #[arg(long, value_name = "S", default_value_t = 1.0)]
+    value_sigma: f64,
+    /// Empirical: file of `<bytes> <count>` lines to draw sizes from
+    #[arg(long, value_name = "FILE")]
+    value_sizes_file: Option<PathBuf>,
     /// Number of distinct keys per thread (reduce contention)
     #[arg(long, default_value_t = 64)]
     keys_per_thread: usize,
+    /// All threads use one key space of N keys instead of private keys each
+    #[arg(long, value_name = "N")]
+    shared_keys: Option<usize>,
+    /// How each op picks its key
+    #[arg(long, value_enum, default_value_t = KeyDist::Sequential)]
+    key_dist: KeyDist,
+    /// Zipfian skew: the key of rank r is picked with weight 1/r^S
+    #[arg(long, value_name = "S", default_value_t = 0.99)]
+    zipf_skew: f64,
+    /// Hotspot: fraction of the keys that are hot
+    #[arg(long, value_name = "FRAC", default_value_t = 0.2)]
+    hot_keys: f64,
+    /// Hotspot: fraction of ops that go to the hot keys
+    #[arg(long, value_name = "FRAC", default_value_t = 0.8)]
+    hot_ops: f64,
     /// Warmup ops per thread (not measured)
     #[arg(long, default_value_t = 5_000)]
     warmup: usize,
     /// Print per-op CSV (op,us) to stdout
     #[arg(long)]
     csv: bool,
+    /// Open loop: send this many requests/s in total, on a schedule, and
+    /// measure latency from each request's intended send time
+    #[arg(long, value_name = "OPS")]
+    rate: Option<f64>,
+    /// Arrival timeline for --rate
+    #[arg(long, value_enum, default_value_t = Arrival::Fixed, requires = "rate")]
+    arrival: Arrival,
+    /// Step mode: after each step raise the rate by OPS, until a step falls
+    /// short of its target (saturation) or --max-rate
+    #[arg(long, value_name = "OPS", requires = "rate")]
+    step_rate: Option<f64>,
+    /// Length of each step
+    #[arg(long, value_name = "SECS", default_value_t = 5.0)]
+    step_secs: f64,
+    /// Highest rate to try in step mode
+    #[arg(long, value_name = "OPS")]
+    max_rate: Option<f64>,
+    /// Save the latency histogram (ns) to FILE; in step mode one file per
+    /// step, FILE.<target rate>
+    #[arg(long, value_name = "FILE")]
+    save_hist: Option<PathBuf>,
+    /// Result format; json has parameters, environment and results in one
+    /// object, for `kvz_bench compare`
+    #[arg(long, value_enum, default_value_t = Output::Text)]
+    output: Output,
+    #[command(flatten)]
+    curve: CurveClientArgs,
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Merge saved latency histograms and print their summary
+    Hist {
+        /// Files written by --save-hist
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Save the merged histogram to FILE
+        #[arg(long, value_name = "FILE")]
+        save_hist: Option<PathBuf>,
+    },
+    /// Compare two `--output json` results; exit non-zero on regressions
+    Compare {
+        /// Baseline result
+        base: PathBuf,
+        /// Result to check against the baseline
+        new: PathBuf,
+        /// Change in percent beyond which a metric counts as regressed
+        #[arg(long, value_name = "PCT", default_value_t = 5.0)]
+        threshold: f64,
+    },
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    /// Human-readable tables
+    Text,
+    /// One JSON object
+    Json,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Arrival {
+    /// Evenly spaced requests
+    Fixed,
+    /// Exponentially distributed gaps (Poisson process)
+    Poisson,
+}
+
+/// What a timed request got back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    GetOk,
+    GetMiss,
+    PutOk,
+    PutStale,
+}
+
+impl Outcome {
+    const GET: [Outcome; 2] = [Outcome::GetOk, Outcome::GetMiss];
+    const PUT: [Outcome; 2] = [Outcome::PutOk, Outcome::PutStale];
+
+    fn reply(self) -> &'static str {
+        match self {
+            Outcome::GetOk | Outcome::PutOk => "OK",
+            Outcome::GetMiss => "MISS",
+            Outcome::PutStale => "STALE",
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyDist {
+    /// Cycle through the keys in order
+    Sequential,
+    /// Every key equally likely
+    Uniform,
+    /// Few keys take most ops (--zipf-skew)
+    Zipfian,
+    /// --hot-ops of the ops go to --hot-keys of the keys
+    Hotspot,
+}
+
+/// The keys the workers use and how they pick one for each op.
+struct KeySpace {
+    dist: KeyDist,
+    /// Keys per worker, or in total if shared.
+    len: usize,
+    /// Key names when all workers share them.
+    shared: Option<Arc<Vec<String>>>,
+    threads: usize,
+    skew: f64,
+    /// Zipfian: cumulative probability of ranks 0..=i.
+    cdf: Vec<f64>,
+    /// Hotspot: keys `0..hot` are hot.
+    hot: usize,
+    hot_ops: f64,
+    /// Next PUT timestamp. One clock for all workers keeps writes to a
+    /// shared key in order across threads.
+    clock: AtomicU64,
+}
+
+impl KeySpace {
+    fn new(args: &Args) -> Result<KeySpace> {
+        let len = args.shared_keys.unwrap_or(args.keys_per_thread);
+        if len == 0 {
+            return Err(anyhow!("--keys-per-thread and --shared-keys must be at least 1"));
+        }
+        let fraction = |x: f64| x > 0.0 && x <= 1.0;
+        if !fraction(args.hot_keys) || !fraction(args.hot_ops) {
+            return Err(anyhow!("--hot-keys and --hot-ops must be in (0.0, 1.0]"));
+        }
+        if !(args.zipf_skew >= 0.0 && args.zipf_skew.is_finite()) {
+            return Err(anyhow!("--zipf-skew must be at least 0"));
+        }
+        let mut cdf = Vec::new();
+        if args.key_dist == KeyDist::Zipfian {
+            let mut sum = 0.0;
+            cdf = (1..=len)
+                .map(|r| {
+                    sum += (r as f64).powf(-args.zipf_skew);
+                    sum
+                })
+                .collect();
+            cdf.iter_mut().for_each(|c| *c /= sum);
+        }
+        let shared = args.shared_keys.map(|n| Arc::new((0..n).map(|i| format!("bench-shared-{i}")).collect()));
+        Ok(KeySpace {
+            dist: args.key_dist,
+            len,
+            shared,
+            threads: args.threads,
+            skew: args.zipf_skew,
+            cdf,
+            hot: ((len as f64 * args.hot_keys).ceil() as usize).clamp(1, len),
+            hot_ops: args.hot_ops,
+            clock: AtomicU64::new(now_millis()),
+        })
+    }
+
+    /// The shared keys, or a new private set for worker `tid`.
+    fn keys(&self, tid: usize, rng: &mut StdRng) -> Arc<Vec<String>> {
+        if let Some(keys) = &self.shared {
+            return Arc::clone(keys);
+        }
+        let rand_suffix: String = (0..6).map(|_| rng.sample(Alphanumeric) as char).collect();
+        let key_prefix: String = format!("bench-{}-{}-", tid, rand_suffix);
+        Arc::new((0..self.len).map(|i| format!("{}{}", key_prefix, i)).collect())
+    }
+
+    /// The `i`th key of worker `tid` in order; shared keys are interleaved
+    /// across workers so each one starts at a different key.
+    fn sequential(&self, tid: usize, i: usize) -> usize {
+        match self.shared {
+            Some(_) => (i * self.threads + tid) % self.len,
+            None => i % self.len,
+        }
+    }
+
+    /// The key for op `i` of worker `tid`.
+    fn pick(&self, tid: usize, i: usize, rng: &mut StdRng) -> usize {
+        match self.dist {
+            KeyDist::Sequential => self.sequential(tid, i),
+            KeyDist::Uniform => rng.gen_range(0..self.len),
+            KeyDist::Zipfian => {
+                let u: f64 = rng.r#gen();
+                self.cdf.partition_point(|&c| c < u).min(self.len - 1)
+            }
+            KeyDist::Hotspot if self.hot == self.len => rng.gen_range(0..self.len),
+            KeyDist::Hotspot if rng.gen_bool(self.hot_ops) => rng.gen_range(0..self.hot),
+            KeyDist::Hotspot => rng.gen_range(self.hot..self.len),
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn describe(&self) -> String {
+        let keys = match self.shared {
+            Some(_) => format!("{} shared", self.len),
+            None => format!("{} per thread", self.len),
+        };
+        match self.dist {
+            KeyDist::Zipfian => format!("{keys}, zipfian (skew {})", self.skew),
+            KeyDist::Hotspot => format!(
+                "{keys}, hotspot ({:.0}% of ops on {} keys)",
+                self.hot_ops * 100.0,
+                self.hot
+            ),
+            KeyDist::Sequential => format!("{keys}, sequential"),
+            KeyDist::Uniform => format!("{keys}, uniform"),
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum ValueDist {
+    /// Every value --value-size bytes
+    Fixed,
+    /// Any size from --value-min to --value-max, equally likely
+    Uniform,
+    /// Log-normal around --value-size with --value-sigma, clamped to
+    /// --value-min..=--value-max
+    Lognormal,
+    /// Sizes in proportion to the counts in --value-sizes-file
+    Empirical,
+}
+
+/// Draws the size of each PUT value.
+struct ValueSizes {
+    dist: ValueDist,
+    fixed: usize,
+    min: usize,
+    max: usize,
+    sigma: f64,
+    /// Empirical: sizes and the cumulative share of each.
+    sizes: Vec<usize>,
+    cdf: Vec<f64>,
+    file: Option<PathBuf>,
+}
+
+impl ValueSizes {
+    fn new(args: &Args) -> Result<ValueSizes> {
+        let mut vs = ValueSizes {
+            dist: args.value_dist,
+            fixed: args.value_size,
+            min: args.value_min,
+            max: args.value_max,
+            sigma: args.value_sigma,
+            sizes: Vec::new(),
+            cdf: Vec::new(),
+            file: args.value_sizes_file.clone(),
+        };
+        match vs.dist {
+            ValueDist::Fixed => {}
+            ValueDist::Uniform | ValueDist::Lognormal => {
+                if vs.min > vs.max {
+                    return Err(anyhow!("--value-min must not exceed --value-max"));
+                }
+                if !(vs.sigma >= 0.0 && vs.sigma.is_finite()) {
+                    return Err(anyhow!("--value-sigma must be at least 0"));
+                }
+            }
+            ValueDist::Empirical => {
+                let path = vs.file.clone().ok_or_else(|| anyhow!("--value-dist empirical needs --value-sizes-file"))?;
+                vs.load(&path).with_context(|| format!("--value-sizes-file {}", path.display()))?;
+            }
+        }
+        Ok(vs)
+    }
+
+    /// Read `<bytes> <count>` lines; blank lines and `#` comments are skipped.
+    fn load(&mut self, path: &Path) -> Result<()> {
+        let text = std::fs::read_to_string(path)?;
+        let mut total = 0.0;
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (Some(size), Some(count), None) = (fields.next(), fields.next(), fields.next()) else {
+                return Err(anyhow!("line {}: expected <bytes> <count>", n + 1));
+            };
+            let size: usize = size.parse().with_context(|| format!("line {}: bad size {size:?}", n + 1))?;
+            let count: f64 = count.parse().with_context(|| format!("line {}: bad count {count:?}", n + 1))?;
+            if !(count >= 0.0 && count.is_finite()) {
+                return Err(anyhow!("line {}: count must be at least 0", n + 1));
+            }
+            total += count;
+            self.sizes.push(size);
+            self.cdf.push(total);
+        }
+        if total <= 0.0 {
+            return Err(anyhow!("no sizes with a positive count"));
+        }
+        self.cdf.iter_mut().for_each(|c| *c /= total);
+        Ok(())
+    }
+
+    /// Largest size that can be drawn.
+    fn largest(&self) -> usize {
+        match self.dist {
+            ValueDist::Fixed => self.fixed,
+            ValueDist::Uniform | ValueDist::Lognormal => self.max,
+            ValueDist::Empirical => self.sizes.iter().copied().max().unwrap_or(0),
+        }
+    }
+
+    fn sample(&self, rng: &mut StdRng) -> usize {
+        match self.dist {
+            ValueDist::Fixed => self.fixed,
+            ValueDist::Uniform => rng.gen_range(self.min..=self.max),
+            ValueDist::Lognormal => {
+                // Box-Muller standard normal; 1 - U avoids ln(0).
+                let (u1, u2): (f64, f64) = (1.0 - rng.r#gen::<f64>(), rng.r#gen());
+                let z = (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos();
+                let size = self.fixed as f64 * (self.sigma * z).exp();
+                (size.round() as usize).clamp(self.min, self.max)
+            }
+            ValueDist::Empirical => {
+                let u: f64 = rng.r#gen();
+                self.sizes[self.cdf.partition_point(|&c| c < u).min(self.sizes.len() - 1)]
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self.dist {
+            ValueDist::Fixed => format!("{} B", self.fixed),
+            ValueDist::Uniform => format!("uniform {}..={} B", self.min, self.max),
+            ValueDist::Lognormal => {
+                format!("lognormal, median {} B, sigma {}, {}..={} B", self.fixed, self.sigma, self.min, self.max)
+            }
+            ValueDist::Empirical => format!(
+                "empirical, {} sizes up to {} B from {}",
+                self.sizes.len(),
+                self.largest(),
+                self.file.as_deref().unwrap_or(Path::new("")).display()
+            ),
+        }
+    }
+}
+
+/// A timed request: its outcome, the key used and the value bytes moved.
+struct Done {
+    outcome: Outcome,
+    key: usize,
+    bytes: usize,
 }
 
 #[derive(Default, Clone)]
 struct Stats {
-    lat_us: Vec<u32>, // microseconds per op
-    puts: usize,
-    gets: usize,
+    /// Latency of every timed request, nanoseconds.
+    lat: Histogram,
+    /// Latency by outcome, indexed by `Outcome as usize`.
+    by_outcome: [Histogram; 4],
+    /// PUTs resent with a newer timestamp after STALE (not timed).
+    retries: usize,
+    /// PUTs seeding a key after a GET MISS (not timed).
+    seeds: usize,
+    /// Time spent on retries and seeds.
+    settle: Duration,
+    /// Value bytes sent by timed PUTs and received by timed GETs.
+    bytes: u64,
 }
 
 impl Stats {
-    fn merge(mut self, mut other: Stats) -> Stats {
-        self.lat_us.append(&mut other.lat_us);
-        self.puts += other.puts;
-        self.gets += other.gets;
+    fn record(&mut self, done: &Done, ns: u64) {
+        self.lat.record(ns);
+        self.by_outcome[done.outcome as usize].record(ns);
+        self.bytes += done.bytes as u64;
+    }
+
+    fn merge(mut self, other: Stats) -> Stats {
+        self.lat.merge(&other.lat);
+        for (a, b) in self.by_outcome.iter_mut().zip(&other.by_outcome) {
+            a.merge(b);
+        }
+        self.retries += other.retries;
+        self.seeds += other.seeds;
+        self.settle += other.settle;
+        self.bytes += other.bytes;
         self
     }
 
-    fn summarize(&mut self) -> Summary {
-        self.lat_us.sort_unstable();
-        let n = self.lat_us.len().max(1);
-        let idx = |q: f64| -> usize {
-            let p = (q * (n as f64 - 1.0)).clamp(0.0, (n - 1) as f64).round() as usize;
-            p
-        };
-        let p50 = self.lat_us[idx(0.50)];
-        let p95 = self.lat_us[idx(0.95)];
-        let p99 = self.lat_us[idx(0.99)];
-        let p999 = self.lat_us[idx(0.999)];
-        let p9999 = self.lat_us[idx(0.9999)];
-        let max = *self.lat_us.last().unwrap();
-        let avg = (self.lat_us.iter().map(|&x| x as u64).sum::<u64>() as f64) / n as f64;
-        Summary { p50, p95, p99, p999, p9999, max, avg_us: avg, puts: self.puts, gets: self.gets, count: n }
+    fn outcome(&self, outcome: Outcome) -> &Histogram {
+        &self.by_outcome[outcome as usize]
+    }
+
+    /// Latency of all GETs or all PUTs.
+    fn command(&self, outcomes: [Outcome; 2]) -> Histogram {
+        let mut h = self.outcome(outcomes[0]).clone();
+        h.merge(self.outcome(outcomes[1]));
+        h
+    }
+
+    fn summarize(&self) -> Summary {
+        Summary::of(&self.lat)
+    }
+
+    /// Latency rows as in [`Stats::print_latency`], keyed `all`, `get`,
+    /// `get_ok`, ..., empty rows left out.
+    fn latency_json(&self) -> Value {
+        let mut rows = Map::new();
+        rows.insert("all".to_string(), Summary::of(&self.lat).json(&self.lat));
+        for (command, outcomes) in [("get", Outcome::GET), ("put", Outcome::PUT)] {
+            let total = self.command(outcomes);
+            if total.is_empty() {
+                continue;
+            }
+            rows.insert(command.to_string(), Summary::of(&total).json(&total));
+            for o in outcomes {
+                let h = self.outcome(o);
+                if !h.is_empty() {
+                    rows.insert(format!("{command}_{}", o.reply().to_lowercase()), Summary::of(h).json(h));
+                }
+            }
+        }
+        Value::Object(rows)
+    }
+
+    /// Latency table: all requests, then per command and reply kind.
+    fn print_latency(&self) {
+        Summary::header();
+        Summary::row("all", &self.lat);
+        for (command, outcomes) in [("GET", Outcome::GET), ("PUT", Outcome::PUT)] {
+            let total = self.command(outcomes);
+            if total.is_empty() {
+                continue;
+            }
+            Summary::row(command, &total);
+            for o in outcomes {
+                if !self.outcome(o).is_empty() {
+                    Summary::row(&format!("  {}", o.reply()), self.outcome(o));
+                }
+            }
+        }
     }
 }
 
+/// Latency percentiles, microseconds.
 struct Summary {
-    p50: u32,
-    p95: u32,
-    p99: u32,
-    p999: u32,
-    p9999: u32,
-    max: u32,
-    avg_us: f64,
-    puts: usize,
-    gets: usize,
-    #[allow(dead_code)]
-    count: usize,
+    p50: f64,
+    p95: f64,
+    p99: f64,
+    p999: f64,
+    p9999: f64,
+    max: f64,
+    avg: f64,
+}
+
+impl Summary {
+    fn of(lat: &Histogram) -> Summary {
+        let us = |ns: u64| ns as f64 / 1e3;
+        Summary {
+            p50: us(lat.quantile(0.50)),
+            p95: us(lat.quantile(0.95)),
+            p99: us(lat.quantile(0.99)),
+            p999: us(lat.quantile(0.999)),
+            p9999: us(lat.quantile(0.9999)),
+            max: us(lat.max()),
+            avg: lat.mean() / 1e3,
+        }
+    }
+
+    fn json(&self, lat: &Histogram) -> Value {
+        json!({
+            "count": lat.len(),
+            "p50": self.p50,
+            "p95": self.p95,
+            "p99": self.p99,
+            "p99.9": self.p999,
+            "p99.99": self.p9999,
+            "max": self.max,
+            "avg": self.avg,
+        })
+    }
+
+    fn header() {
+        println!(
+            "{:<14} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}",
+            "latency (us)", "count", "p50", "p95", "p99", "p99.9", "p99.99", "max", "avg"
+        );
+    }
+
+    fn row(label: &str, lat: &Histogram) {
+        let s = Summary::of(lat);
+        println!(
+            "{label:<14} {:>9} {:>9.1} {:>9.1} {:>9.1} {:>9.1} {:>9.1} {:>9.1} {:>9.1}",
+            lat.len(),
+            s.p50,
+            s.p95,
+            s.p99,
+            s.p999,
+            s.p9999,
+            s.max,
+            s.avg
+        );
+    }
+}
+
+/// How a thread issues its timed requests.
+#[derive(Debug, Clone, Copy)]
+enum Pace {
+    /// Closed loop: next request as soon as the reply arrives.
+    Closed { ops: usize },
+    /// Open loop: requests scheduled at `rate`/s for `ops` requests or until
+    /// `until` has passed, latency counted from the scheduled time. The
+    /// schedule starts `phase` of a gap late, so threads at the same rate
+    /// interleave their arrivals instead of sending together.
+    Open { rate: f64, phase: f64, arrival: Arrival, ops: usize, until: Option<Duration> },
+}
+
+/// One client connection with its keys and payload.
+struct Worker {
+    sock: zmq::Socket,
+    rng: StdRng,
+    /// Payload buffer of the largest value size; PUTs send a prefix.
+    value: Vec<u8>,
+    sizes: Arc<ValueSizes>,
+    space: Arc<KeySpace>,
+    keys: Arc<Vec<String>>,
+    tid: usize,
+    get_ratio: f64,
+    /// Ops issued so far, to cycle through keys.
+    n: usize,
+}
+
+impl Worker {
+    fn connect(
+        ctx: &zmq::Context,
+        args: &Args,
+        space: &Arc<KeySpace>,
+        sizes: &Arc<ValueSizes>,
+        tid: usize,
+    ) -> Result<Worker> {
+        let sock = ctx.socket(zmq::REQ)?;
+        args.curve.apply(&sock)?;
+        sock.connect(&args.connect).with_context(|| format!("connect {}", args.connect))?;
+
+        // Thread-local RNG and data buffer
+        let mut rng = StdRng::seed_from_u64(0xC0FFEE + tid as u64);
+        let mut value = vec![0u8; sizes.largest()];
+        rng.fill(&mut value[..]);
+
+        // Build key set for this thread
+        let keys = space.keys(tid, &mut rng);
+        Ok(Worker {
+            sock,
+            rng,
+            value,
+            sizes: Arc::clone(sizes),
+            space: Arc::clone(space),
+            keys,
+            tid,
+            get_ratio: args.get_ratio,
+            n: 0,
+        })
+    }
+
+    /// Size of the next PUT value.
+    fn next_size(&mut self) -> usize {
+        self.sizes.sample(&mut self.rng)
+    }
+
+    /// Preload/warm keys with PUT so GETs will hit
+    fn warmup(&mut self, ops: usize) -> Result<()> {
+        for i in 0..ops {
+            let size = self.next_size();
+            let k = &self.keys[self.space.sequential(self.tid, i)];
+            zmq_put(&self.sock, k, self.space.tick(), &self.value[..size])?;
+            if i % 128 == 0 {
+                // mutate payload a bit
+                let j = self.rng.gen_range(0..self.value.len().max(1));
+                if let Some(b) = self.value.get_mut(j) {
+                    *b ^= (i as u8).wrapping_mul(31);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// One GET or PUT, chosen by ratio (deterministic via RNG); this is
+    /// the part that gets timed.
+    fn op(&mut self) -> Result<Done> {
+        let i = self.n;
+        self.n += 1;
+        let do_get = self.rng.gen_bool(self.get_ratio);
+        let k = self.space.pick(self.tid, i, &mut self.rng);
+        if do_get {
+            return Ok(match zmq_get(&self.sock, &self.keys[k])? {
+                Some((_, v)) => Done { outcome: Outcome::GetOk, key: k, bytes: v.len() },
+                None => Done { outcome: Outcome::GetMiss, key: k, bytes: 0 },
+            });
+        }
+        let size = self.next_size();
+        // small mutation to avoid identical payloads
+        if !self.value.is_empty() {
+            let pos = (i + self.tid) % self.value.len();
+            self.value[pos] ^= (i as u8).wrapping_mul(13);
+        }
+        let outcome = match zmq_put(&self.sock, &self.keys[k], self.space.tick(), &self.value[..size])? {
+            PutReply::Ok => Outcome::PutOk,
+            PutReply::Stale => Outcome::PutStale,
+        };
+        Ok(Done { outcome, key: k, bytes: size })
+    }
+
+    /// Follow-up after a timed op, outside the measurement and counted in
+    /// `stats`: a MISS seeds the key, a STALE PUT (clock skew) is resent once
+    /// with a newer timestamp. Returns the time it took.
+    fn settle(&mut self, done: &Done, stats: &mut Stats) -> Result<Duration> {
+        let t0 = Instant::now();
+        let key = &self.keys[done.key];
+        match done.outcome {
+            Outcome::GetMiss => {
+                let size = self.sizes.sample(&mut self.rng);
+                zmq_put(&self.sock, key, self.space.tick(), &self.value[..size])?;
+                stats.seeds += 1;
+            }
+            Outcome::PutStale => {
+                zmq_put(&self.sock, key, self.space.tick(), &self.value[..done.bytes])?;
+                stats.retries += 1;
+            }
+            Outcome::GetOk | Outcome::PutOk => return Ok(Duration::ZERO),
+        }
+        let took = t0.elapsed();
+        stats.settle += took;
+        Ok(took)
+    }
+
+    fn run(&mut self, pace: Pace) -> Result<Stats> {
+        let mut stats = Stats::default();
+        match pace {
+            Pace::Closed { ops } => {
+                for _ in 0..ops {
+                    let t0 = Instant::now();
+                    let done = self.op()?;
+                    stats.record(&done, elapsed_ns(t0));
+                    self.settle(&done, &mut stats)?;
+                }
+            }
+            Pace::Open { rate, phase, arrival, ops, until } => {
+                let start = Instant::now();
+                let mut due = start + Duration::from_secs_f64(phase / rate);
+                // The timeline pauses during follow-ups, so they neither
+                // delay later requests nor shorten a step.
+                let mut paused = Duration::ZERO;
+                for _ in 0..ops {
+                    let gap = match arrival {
+                        Arrival::Fixed => 1.0 / rate,
+                        // Inverse-CDF sample of an exponential gap; 1 - U avoids ln(0).
+                        Arrival::Poisson => -(1.0 - self.rng.r#gen::<f64>()).ln() / rate,
+                    };
+                    due += Duration::from_secs_f64(gap);
+                    // A step ends on time even if requests are backed up, so
+                    // a server that cannot keep up completes fewer of them.
+                    if until.is_some_and(|u| due - start - paused > u || start.elapsed().saturating_sub(paused) > u) {
+                        break;
+                    }
+                    let now = Instant::now();
+                    if due > now {
+                        thread::sleep(due - now);
+                    }
+                    // A late send still counts from when it was due, so a slow
+                    // reply shows up in the requests it delayed.
+                    let done = self.op()?;
+                    stats.record(&done, elapsed_ns(due));
+                    let took = self.settle(&done, &mut stats)?;
+                    due += took;
+                    paused += took;
+                }
+            }
+        }
+        Ok(stats)
+    }
 }
 
+/// A worker's results for one step in step mode.
+type StepResult = Result<Stats, String>;
+
 fn main() -> Result<()> {
     let args = Args::parse();
+    args.log.init()?;
+    match &args.command {
+        Some(Command::Hist { files, save_hist }) => return hist_mode(files, save_hist.as_deref()),
+        Some(Command::Compare { base, new, threshold }) => return compare_mode(base, new, *threshold),
+        None => {}
+    }
     if !(0.0..=1.0).contains(&args.get_ratio) {
         return Err(anyhow!("--get-ratio must be between 0.0 and 1.0"));
     }
+    if args.threads == 0 {
+        return Err(anyhow!("--threads must be at least 1"));
+    }
+    let positive = |x: f64| x > 0.0 && x.is_finite();
+    if args.rate.is_some_and(|r| !positive(r)) {
+        return Err(anyhow!("--rate must be positive"));
+    }
+    if args.step_rate.is_some_and(|s| !positive(s)) || !positive(args.step_secs) {
+        return Err(anyhow!("--step-rate and --step-secs must be positive"));
+    }
+    if args.step_rate.is_some() {
+        return step_mode(&args);
+    }
 
     // One context shared across threads (as recommended by ZeroMQ)
     let ctx = Arc::new(zmq::Context::new());
+    let space = Arc::new(KeySpace::new(&args)?);
+    let sizes = Arc::new(ValueSizes::new(&args)?);
     let barrier = Arc::new(Barrier::new(args.threads));
-    let start_barrier = Arc::new(Barrier::new(args.threads));
+    // Main joins the start barrier to time the run from there.
+    let start_barrier = Arc::new(Barrier::new(args.threads + 1));
 
     // Pre-generate value payload (same size; content varies per thread to avoid dedup illusions)
     let mut handles = Vec::with_capacity(args.threads);
+    let started = now_millis();
     let bench_start = Instant::now();
 
     for tid in 0..args.threads {
         let ctx = Arc::clone(&ctx);
+        let space = Arc::clone(&space);
+        let sizes = Arc::clone(&sizes);
         let barrier = Arc::clone(&barrier);
         let start_barrier = Arc::clone(&start_barrier);
         let args = args.clone();
 
-        handles.push(thread::spawn(move || -> Result<Stats> {
-            let sock = ctx.socket(zmq::REQ)?;
-            sock.connect(&args.connect).with_context(|| format!("connect {}", args.connect))?;
-
-            // Thread-local RNG and data buffer
-            let mut rng = StdRng::seed_from_u64(0xC0FFEE + tid as u64);
-            let mut value = vec![0u8; args.value_size];
-            rng.fill(&mut value[..]);
+        handles.push(thread::spawn(move || -> Result<(Stats, Instant)> {
+            let worker = Worker::connect(&ctx, &args, &space, &sizes, tid).and_then(|mut w| w.warmup(args.warmup).map(|()| w));
 
-            // Build key set for this thread
-            let rand_suffix: String = (0..6)
-                .map(|_| rng.sample(Alphanumeric) as char)
-                .collect();
-            let key_prefix: String = format!(
-                "bench-{}-{}-",
-                tid,
-                rand_suffix
-            );
-            let keys: Vec<String> = (0..args.keys_per_thread)
-                .map(|i| format!("{}{}", key_prefix, i))
-                .collect();
-
-            // Preload/warm keys with PUT so GETs will hit
-            let base_ts = now_millis();
-            for i in 0..args.warmup {
-                let k = &keys[i % keys.len()];
-                let ts = base_ts + i as u64;
-                zmq_put(&sock, k, ts, &value)?;
-                if i % 128 == 0 {
-                    // mutate payload a bit
-                    let j = rng.gen_range(0..value.len());
-                    value[j] ^= (i as u8).wrapping_mul(31);
-                }
-            }
-
-            // Sync start across threads; also give server a moment to drain warmup
+            // Sync start across threads, even after a failure so the others
+            // are not left waiting; also give server a moment to drain warmup
             barrier.wait();
             thread::sleep(Duration::from_millis(50));
             start_barrier.wait();
+            let mut worker = worker?;
 
             // Timed run
-            let mut stats = Stats::default();
-            stats.lat_us.reserve(args.iters);
-
-            let mut ts_counter = base_ts + args.warmup as u64;
-            let mut value = value; // reuse
-            for i in 0..args.iters {
-                // Choose op by ratio (deterministic via RNG)
-                let do_get = rng.gen_bool(args.get_ratio);
-                let key = &keys[i % keys.len()];
-
-                let t0 = Instant::now();
-                if do_get {
-                    let (_ts, _data) = match zmq_get(&sock, key)? {
-                        Some(x) => x,
-                        None => {
-                            // On MISS (shouldn't happen), do a PUT to seed it
-                            zmq_put(&sock, key, ts_counter, &value)?;
-                            (ts_counter, value.clone())
-                        }
-                    };
-                } else {
-                    ts_counter += 1;
-                    // small mutation to avoid identical payloads
-                    if !value.is_empty() {
-                        let pos = (i + tid) % value.len();
-                        value[pos] ^= (i as u8).wrapping_mul(13);
-                    }
-                    let rep = zmq_put(&sock, key, ts_counter, &value)?;
-                    // If server says STALE (clock skew), bump ts and retry once (not timed separately)
-                    if matches!(rep, PutReply::Stale) {
-                        ts_counter += 1;
-                        zmq_put(&sock, key, ts_counter, &value)?;
-                    }
-                    stats.puts += 1;
+            let pace = match args.rate {
+                Some(rate) => {
+                    let (rate, phase) = (rate / args.threads as f64, tid as f64 / args.threads as f64);
+                    Pace::Open { rate, phase, arrival: args.arrival, ops: args.iters, until: None }
                 }
-                let dt = t0.elapsed();
-                stats.lat_us.push(duration_to_us(dt) as u32);
-                if do_get {
-                    stats.gets += 1;
-                }
-            }
-
-            Ok(stats)
+                None => Pace::Closed { ops: args.iters },
+            };
+            let stats = worker.run(pace)?;
+            Ok((stats, Instant::now()))
         }));
     }
 
-    // Collect results
+    // Collect results; the timed section runs from the start barrier until
+    // the last worker finishes.
+    start_barrier.wait();
+    let timed_start = Instant::now();
+    let mut timed_end = timed_start;
     let mut agg = Stats::default();
-    let timed_span = {
-        let t0 = Instant::now();
-        for h in handles {
-            match h.join() {
-                Ok(Ok(s)) => agg = agg.merge(s),
-                Ok(Err(e)) => eprintln!("worker error: {e:?}"),
-                Err(_) => eprintln!("worker panic"),
+    let mut failed = 0;
+    for h in handles {
+        match h.join() {
+            Ok(Ok((s, end))) => {
+                agg = agg.merge(s);
+                timed_end = timed_end.max(end);
+            }
+            Ok(Err(e)) => {
+                error!(error = %format!("{e:#}"), "worker failed");
+                failed += 1;
+            }
+            Err(_) => {
+                error!("worker panicked");
+                failed += 1;
             }
         }
-        t0.elapsed()
-    };
+    }
+    if failed == args.threads {
+        return Err(anyhow!("all {failed} workers failed"));
+    }
+    let timed_span = timed_end - timed_start;
 
     // Summary
-    let total_ops = agg.lat_us.len();
+    let total_ops = agg.lat.len();
     let wall = bench_start.elapsed();
     let throughput = (total_ops as f64) / wall.as_secs_f64();
     let timed_throughput = (total_ops as f64) / timed_span.as_secs_f64();
-    let sum = agg.summarize();
+    let mb_per_sec = agg.bytes as f64 / 1e6 / timed_span.as_secs_f64();
+    if let Some(path) = &args.save_hist {
+        agg.lat.save(path)?;
+    }
 
+    if args.output == Output::Json {
+        let results = json!({
+            "mode": if args.rate.is_some() { "open" } else { "closed" },
+            "ops": total_ops,
+            "gets": agg.command(Outcome::GET).len(),
+            "puts": agg.command(Outcome::PUT).len(),
+            "retries": agg.retries,
+            "seeds": agg.seeds,
+            "settle_secs": agg.settle.as_secs_f64(),
+            "wall_secs": wall.as_secs_f64(),
+            "timed_secs": timed_span.as_secs_f64(),
+            "ops_per_sec": timed_throughput,
+            "wall_ops_per_sec": throughput,
+            "mb_per_sec": mb_per_sec,
+            "latency_us": agg.latency_json(),
+        });
+        println!("{}", serde_json::to_string_pretty(&report(&args, &space, &sizes, started, results))?);
+        return Ok(());
+    }
     println!("== kvz latency benchmark ==");
     println!("endpoint       : {}", args.connect);
     println!("threads        : {}", args.threads);
     println!("iters/thread   : {}", args.iters);
     println!("get_ratio      : {:.3}", args.get_ratio);
-    println!("value_size     : {} B", args.value_size);
-    println!("keys/thread    : {}", args.keys_per_thread);
+    println!("values         : {}", sizes.describe());
+    println!("keys           : {}", space.describe());
     println!("warmup/thread  : {}", args.warmup);
+    match args.rate {
+        Some(rate) => println!("mode           : open loop, {rate:.0} ops/s target, {:?} arrivals", args.arrival),
+        None => println!("mode           : closed loop"),
+    }
     println!();
     println!("ops total      : {}", total_ops);
-    println!("ops GET/PUT    : {}/{}", sum.gets, sum.puts);
-    println!("throughput     : {:>8.0} ops/s (total wall)", throughput);
-    println!("throughput     : {:>8.0} ops/s (timed section)", timed_throughput);
+    println!("ops GET/PUT    : {}/{}", agg.command(Outcome::GET).len(), agg.command(Outcome::PUT).len());
     println!(
-        "latency (us)   : p50 {:>6}  p95 {:>6}  p99 {:>6}  p99.9 {:-6} p999.9 {:>6} max {:>6}  avg {:>7.1}",
-        sum.p50, sum.p95, sum.p99, sum.p999, sum.p9999, sum.max, sum.avg_us
+        "retries        : {} PUT after STALE, {} seeding PUT after MISS (not timed, {:.3} s over all threads)",
+        agg.retries,
+        agg.seeds,
+        agg.settle.as_secs_f64()
     );
+    println!("throughput     : {:>8.0} ops/s (total wall)", throughput);
+    println!("throughput     : {:>8.0} ops/s (timed section)", timed_throughput);
+    println!("throughput     : {:>8.2} MB/s values PUT + GET (timed section)", mb_per_sec);
+    println!();
+    agg.print_latency();
+
+    Ok(())
+}
+
+/// Name of a clap value, as given on the command line.
+fn value_name(v: impl ValueEnum) -> Value {
+    v.to_possible_value().map(|p| p.get_name().to_string()).into()
+}
+
+/// The `--output json` object around a run's `results`.
+fn report(args: &Args, space: &KeySpace, sizes: &ValueSizes, started: u64, results: Value) -> Value {
+    let params = json!({
+        "connect": args.connect,
+        "curve": args.curve.curve_server_key.is_some(),
+        "threads": args.threads,
+        "iters": args.iters,
+        "warmup": args.warmup,
+        "get_ratio": args.get_ratio,
+        "rate": args.rate,
+        "arrival": args.rate.map(|_| args.arrival).map_or(Value::Null, value_name),
+        "step_rate": args.step_rate,
+        "step_secs": args.step_rate.map(|_| args.step_secs),
+        "max_rate": args.max_rate,
+        "keys": space.len,
+        "shared_keys": space.shared.is_some(),
+        "key_dist": value_name(args.key_dist),
+        "zipf_skew": (args.key_dist == KeyDist::Zipfian).then_some(args.zipf_skew),
+        "hot_keys": (args.key_dist == KeyDist::Hotspot).then_some(args.hot_keys),
+        "hot_ops": (args.key_dist == KeyDist::Hotspot).then_some(args.hot_ops),
+        "value_dist": value_name(args.value_dist),
+        "value_size": matches!(sizes.dist, ValueDist::Fixed | ValueDist::Lognormal).then_some(sizes.fixed),
+        "value_min": matches!(sizes.dist, ValueDist::Uniform | ValueDist::Lognormal).then_some(sizes.min),
+        "value_max": sizes.largest(),
+        "value_sigma": (sizes.dist == ValueDist::Lognormal).then_some(sizes.sigma),
+        "value_sizes_file": sizes.file.as_ref().map(|p| p.display().to_string()),
+    });
+    let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| std::fs::read_to_string("/etc/hostname"))
+        .map(|h| h.trim().to_string())
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .unwrap_or_default();
+    let env = json!({
+        "hostname": hostname,
+        "os": std::env::consts::OS,
+        "arch": std::env::consts::ARCH,
+        "cpus": thread::available_parallelism().map_or(0, |n| n.get()),
+        "kvz_version": env!("CARGO_PKG_VERSION"),
+        "build": if cfg!(debug_assertions) { "debug" } else { "release" },
+    });
+    json!({
+        "kvz_bench": REPORT_VERSION,
+        "started": utc_time(started).replacen(' ', "T", 1) + "Z",
+        "params": params,
+        "env": env,
+        "results": results,
+    })
+}
+
+/// Compare two `--output json` reports metric by metric. Throughput may not
+/// drop and latency may not rise by more than `threshold` percent.
+fn compare_mode(base_path: &Path, new_path: &Path, threshold: f64) -> Result<()> {
+    if !(threshold >= 0.0 && threshold.is_finite()) {
+        return Err(anyhow!("--threshold must be at least 0"));
+    }
+    let load = |path: &Path| -> Result<Value> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+        let report: Value = serde_json::from_str(&text).with_context(|| format!("parse {}", path.display()))?;
+        if report["kvz_bench"].as_u64() != Some(REPORT_VERSION) {
+            return Err(anyhow!("{}: not a kvz_bench --output json report", path.display()));
+        }
+        Ok(report)
+    };
+    let (base, new) = (load(base_path)?, load(new_path)?);
+    let started = |r: &Value| r["started"].as_str().unwrap_or("?").to_string();
+    let host = |r: &Value| r["env"]["hostname"].as_str().unwrap_or("?").to_string();
+
+    println!("== kvz benchmark comparison ==");
+    println!("base           : {} ({}, {})", base_path.display(), started(&base), host(&base));
+    println!("new            : {} ({}, {})", new_path.display(), started(&new), host(&new));
+    println!("threshold      : {threshold}%");
+    for (section, key) in [("params", ""), ("env", "build")] {
+        for (k, v) in base[section].as_object().into_iter().flatten() {
+            if !key.is_empty() && k != key {
+                continue;
+            }
+            let other = &new[section][k];
+            if other != v {
+                println!("note           : {k} differs: {v} -> {other}");
+            }
+        }
+    }
+
+    // (name, base, new, higher is better)
+    let mut metrics: Vec<(String, f64, f64, bool)> = Vec::new();
+    let number = |r: &Value, path: &[&str]| path.iter().fold(r, |v, k| &v[*k]).as_f64();
+    for (key, label) in [
+        ("ops_per_sec", "throughput ops/s"),
+        ("mb_per_sec", "throughput MB/s"),
+        ("sustained_ops_per_sec", "sustained ops/s"),
+    ] {
+        if let (Some(b), Some(n)) = (number(&base, &["results", key]), number(&new, &["results", key])) {
+            metrics.push((label.to_string(), b, n, true));
+        }
+    }
+    for row in base["results"]["latency_us"].as_object().into_iter().flat_map(|rows| rows.keys()) {
+        for field in COMPARED {
+            let path = ["results", "latency_us", row.as_str(), field];
+            if let (Some(b), Some(n)) = (number(&base, &path), number(&new, &path)) {
+                metrics.push((format!("latency {row} {field} us"), b, n, false));
+            }
+        }
+    }
+    if metrics.is_empty() {
+        return Err(anyhow!("no metrics in common (were both runs in the same mode?)"));
+    }
+
+    println!();
+    println!("{:<28} {:>12} {:>12} {:>9}", "metric", "base", "new", "change");
+    let mut regressed = 0;
+    for (name, b, n, higher_better) in &metrics {
+        let change = if *b != 0.0 { Some((n - b) / b * 100.0) } else { None };
+        let worse = change.map_or(0.0, |c| if *higher_better { -c } else { c });
+        let mark = if worse > threshold {
+            regressed += 1;
+            "REGRESSED"
+        } else if worse < -threshold {
+            "improved"
+        } else {
+            ""
+        };
+        let change = change.map_or("n/a".to_string(), |c| format!("{c:+.1}%"));
+        println!("{}", format!("{name:<28} {b:>12.1} {n:>12.1} {change:>9}  {mark}").trim_end());
+    }
+    println!();
+    if regressed > 0 {
+        return Err(anyhow!("{regressed} of {} metrics regressed by more than {threshold}%", metrics.len()));
+    }
+    println!("result         : no regressions beyond {threshold}% in {} metrics", metrics.len());
+    Ok(())
+}
+
+/// Merge histograms saved by earlier runs (e.g. one per client host) and
+/// summarize them as one run.
+fn hist_mode(files: &[PathBuf], save: Option<&Path>) -> Result<()> {
+    let mut merged = Histogram::new();
+    for path in files {
+        let h = Histogram::load(path)?;
+        if merged.merge_overflows(&h) {
+            return Err(anyhow!("{}: counts overflow when merged with the earlier files", path.display()));
+        }
+        merged.merge(&h);
+    }
+    println!("== kvz latency histogram ==");
+    for path in files {
+        println!("file           : {}", path.display());
+    }
+    println!();
+    Summary::header();
+    Summary::row("all", &merged);
+    if let Some(path) = save {
+        merged.save(path)?;
+    }
+    Ok(())
+}
+
+/// Ramp the open-loop rate from --rate by --step-rate every --step-secs
+/// until a step achieves less than [`SATURATION`] of its target.
+fn step_mode(args: &Args) -> Result<()> {
+    let start_rate = args.rate.unwrap_or_default();
+    let step = args.step_rate.unwrap_or_default();
+    let step_len = Duration::from_secs_f64(args.step_secs);
+    let text = args.output == Output::Text;
+    let started = now_millis();
+    let ctx = Arc::new(zmq::Context::new());
+    let space = Arc::new(KeySpace::new(args)?);
+    let sizes = Arc::new(ValueSizes::new(args)?);
+    let start_barrier = Arc::new(Barrier::new(args.threads + 1));
+    let (tx, rx) = channel::<StepResult>();
+
+    // After each step a worker waits for main to say whether to go on.
+    let mut go = Vec::with_capacity(args.threads);
+    let mut handles = Vec::with_capacity(args.threads);
+    for tid in 0..args.threads {
+        let ctx = Arc::clone(&ctx);
+        let space = Arc::clone(&space);
+        let sizes = Arc::clone(&sizes);
+        let start_barrier = Arc::clone(&start_barrier);
+        let tx: Sender<StepResult> = tx.clone();
+        let (go_tx, go_rx) = channel::<bool>();
+        go.push(go_tx);
+        let args = args.clone();
+        handles.push(thread::spawn(move || {
+            let worker = Worker::connect(&ctx, &args, &space, &sizes, tid).and_then(|mut w| w.warmup(args.warmup).map(|()| w));
+            start_barrier.wait();
+            let mut worker = match worker {
+                Ok(w) => w,
+                Err(e) => {
+                    let _ = tx.send(Err(format!("{e:#}")));
+                    return;
+                }
+            };
+            for n in 0.. {
+                let rate = (start_rate + step * n as f64) / args.threads as f64;
+                let phase = tid as f64 / args.threads as f64;
+                let pace = Pace::Open { rate, phase, arrival: args.arrival, ops: usize::MAX, until: Some(step_len) };
+                let stats = worker.run(pace).map_err(|e| format!("{e:#}"));
+                let failed = stats.is_err();
+                // A send only fails once main has stopped listening.
+                if tx.send(stats).is_err() || failed || !go_rx.recv().unwrap_or(false) {
+                    break;
+                }
+            }
+        }));
+    }
+    drop(tx);
+    start_barrier.wait();
 
+    if text {
+        println!("== kvz saturation search ==");
+        println!("endpoint       : {}", args.connect);
+        println!("threads        : {}", args.threads);
+        println!("get_ratio      : {:.3}", args.get_ratio);
+        println!("values         : {}", sizes.describe());
+        println!("keys           : {}", space.describe());
+        println!("arrivals       : {:?}, {} s per step", args.arrival, args.step_secs);
+        println!();
+        println!(
+            "{:>10} {:>10} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}",
+            "target/s", "achieved/s", "MB/s", "p50 us", "p99 us", "p99.9 us", "max us", "ops"
+        );
+    }
+    let mut steps = Vec::new();
+    let mut sustained = None;
+    let mut saturated = false;
+    for n in 0.. {
+        let target = start_rate + step * n as f64;
+        let mut agg = Stats::default();
+        let mut ok = 0;
+        for _ in 0..args.threads {
+            match rx.recv() {
+                Ok(Ok(s)) => {
+                    agg = agg.merge(s);
+                    ok += 1;
+                }
+                Ok(Err(e)) => error!(error = %e, "worker failed"),
+                Err(_) => break,
+            }
+        }
+        if ok < args.threads {
+            // The step ran short of connections; its rate means nothing.
+            stop_workers(&go);
+            let failed = args.threads - ok;
+            return Err(anyhow!("{failed} of {} workers failed during the {target:.0} ops/s step", args.threads));
+        }
+        let ops = agg.lat.len();
+        let achieved = ops as f64 / args.step_secs;
+        let sum = agg.summarize();
+        let mb = agg.bytes as f64 / 1e6 / args.step_secs;
+        if text {
+            println!(
+                "{:>10.0} {:>10.0} {:>8.2} {:>8.1} {:>8.1} {:>8.1} {:>8.1} {:>8}",
+                target, achieved, mb, sum.p50, sum.p99, sum.p999, sum.max, ops
+            );
+        }
+        steps.push(json!({
+            "target": target,
+            "achieved": achieved,
+            "mb_per_sec": mb,
+            "ops": ops,
+            "latency_us": agg.latency_json(),
+        }));
+        if let Some(path) = &args.save_hist {
+            agg.lat.save(&PathBuf::from(format!("{}.{target:.0}", path.display())))?;
+        }
+        saturated = achieved < target * SATURATION;
+        if !saturated {
+            sustained = Some(target);
+        }
+        if saturated || args.max_rate.is_some_and(|m| target + step > m) {
+            break;
+        }
+        for g in &go {
+            let _ = g.send(true);
+        }
+    }
+    stop_workers(&go);
+    for h in handles {
+        if h.join().is_err() {
+            error!("worker panicked");
+        }
+    }
+    if !text {
+        let results = json!({
+            "mode": "step",
+            "steps": steps,
+            "saturated": saturated,
+            "sustained_ops_per_sec": sustained,
+        });
+        println!("{}", serde_json::to_string_pretty(&report(args, &space, &sizes, started, results))?);
+        return Ok(());
+    }
+    println!();
+    match (sustained, saturated) {
+        (Some(rate), true) => {
+            println!("saturation     : ~{rate:.0} ops/s sustained (next step fell below {:.0}% of target)", SATURATION * 100.0)
+        }
+        (Some(rate), false) => println!("saturation     : not reached, {rate:.0} ops/s sustained"),
+        (None, _) => println!("saturation     : below the starting rate of {start_rate:.0} ops/s"),
+    }
     Ok(())
 }
 
+fn stop_workers(go: &[Sender<bool>]) {
+    for g in go {
+        let _ = g.send(false);
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum PutReply {
     Ok,
@@ -239,7 +1241,7 @@ enum PutReply {
 }
 
 fn zmq_put(sock: &zmq::Socket, key: &str, ts: u64, data: &[u8]) -> Result<PutReply> {
-    sock.send_multipart(&[b"PUT".as_slice(), key.as_bytes(), &ts.to_be_bytes(), data], 0)?;
+    sock.send_multipart([b"PUT".as_slice(), key.as_bytes(), &ts.to_be_bytes(), data], 0)?;
     let rep = sock.recv_multipart(0)?;
     if rep.is_empty() {
         return Err(anyhow!("empty PUT reply"));
@@ -247,16 +1249,13 @@ fn zmq_put(sock: &zmq::Socket, key: &str, ts: u64, data: &[u8]) -> Result<PutRep
     match std::str::from_utf8(&rep[0]).unwrap_or("") {
         "OK" => Ok(PutReply::Ok),
         "STALE" => Ok(PutReply::Stale),
-        "ERR" => {
-            let msg = rep.get(1).and_then(|b| std::str::from_utf8(b).ok()).unwrap_or("");
-            Err(anyhow!("PUT ERR: {msg}"))
-        }
+        "ERR" => Err(anyhow::Error::new(parse_err(&rep)).context("PUT ERR")),
         x => Err(anyhow!("unexpected PUT reply: {x:?}")),
     }
 }
 
 fn zmq_get(sock: &zmq::Socket, key: &str) -> Result<Option<(u64, Vec<u8>)>> {
-    sock.send_multipart(&[b"GET".as_slice(), key.as_bytes()], 0)?;
+    sock.send_multipart([b"GET".as_slice(), key.as_bytes()], 0)?;
     let rep = sock.recv_multipart(0)?;
     if rep.is_empty() {
         return Err(anyhow!("empty GET reply"));
@@ -272,10 +1271,7 @@ fn zmq_get(sock: &zmq::Socket, key: &str) -> Result<Option<(u64, Vec<u8>)>> {
             let ts = u64::from_be_bytes(tsb);
             Ok(Some((ts, rep[2].clone())))
         }
-        "ERR" => {
-            let msg = rep.get(1).and_then(|b| std::str::from_utf8(b).ok()).unwrap_or("");
-            Err(anyhow!("GET ERR: {msg}"))
-        }
+        "ERR" => Err(anyhow::Error::new(parse_err(&rep)).context("GET ERR")),
         x => Err(anyhow!("unexpected GET reply: {x:?}")),
     }
 }
@@ -290,6 +1286,6 @@ fn now_millis() -> u64 {
 }
 
 #[inline]
-fn duration_to_us(d: Duration) -> u128 {
-    (d.as_secs() as u128) * 1_000_000 + (d.subsec_nanos() as u128) / 1_000
+fn elapsed_ns(since: Instant) -> u64 {
+    since.elapsed().as_nanos().min(u64::MAX as u128) as u64
 }
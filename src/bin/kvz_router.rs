@@ -1,218 +1,264 @@
-use anyhow::{anyhow, Context, Result};
-use clap::Parser;
-use std::collections::HashMap;
-use std::hash::{BuildHasherDefault, Hasher};
-use std::sync::{Arc, RwLock};
+use anyhow::{bail, Context, Result};
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
+use kvz::acl::AclArgs;
+use kvz::auth::{frame_user_id, AuthArgs, ANONYMOUS};
+use kvz::config::{ConfigArgs, Configure, Dump, Overlay};
+use kvz::curve::CurveServerArgs;
+use kvz::hotkeys::HotKeysArgs;
+use kvz::limits::Limits;
+use kvz::listen::{parse_binds, Listeners};
+use kvz::logging::LogArgs;
+use kvz::metrics::{watch_connections, Metrics, MetricsArgs};
+use kvz::monitor::MonitorArgs;
+use kvz::quota::QuotaArgs;
+use kvz::server::{recv_tagged_request, Server, MAX_FRAMES};
+use kvz::slowlog::SlowLogArgs;
+use std::sync::Arc;
 use std::thread;
+use tracing::info;
 
 /// Server with ROUTER/DEALER + worker pool.
-/// Protocol is the same as the simple server:
-///   PUT: ["PUT", key(utf8), ts(8B BE), data]
-///   GET: ["GET", key]
-/// Replies:
-///   PUT -> ["OK"] or ["STALE"] or ["ERR", msg]
-///   GET -> ["OK", ts(8B BE), data] or ["MISS"] or ["ERR", msg]
+/// Protocol is the same as the simple server (see `kvz::server`).
 #[derive(Parser, Debug)]
 #[command(name = "kvz-router")]
 #[command(about = "ZeroMQ K/V store (ROUTER/DEALER worker pool)")]
 struct Args {
-    /// Bind endpoint for client connections (ROUTER)
-    #[arg(long, default_value = "tcp://*:5555")]
-    bind: String,
+    /// Bind endpoint for client connections, ENDPOINT[,curve=FILE|none][,allowlist=FILE|none] (repeatable)
+    #[arg(long, value_name = "ENDPOINT", default_value = "tcp://*:5555")]
+    bind: Vec<String>,
     /// Number of worker threads
     #[arg(long, default_value_t = 8)]
     workers: usize,
     /// Number of shards in the in-memory store
     #[arg(long, default_value_t = 64)]
     shards: usize,
+    #[command(flatten)]
+    limits: Limits,
+    #[command(flatten)]
+    curve: CurveServerArgs,
+    #[command(flatten)]
+    auth: AuthArgs,
+    #[command(flatten)]
+    acl: AclArgs,
+    #[command(flatten)]
+    quota: QuotaArgs,
+    #[command(flatten)]
+    metrics: MetricsArgs,
+    #[command(flatten)]
+    slowlog: SlowLogArgs,
+    #[command(flatten)]
+    monitor: MonitorArgs,
+    #[command(flatten)]
+    hotkeys: HotKeysArgs,
+    #[command(flatten)]
+    log: LogArgs,
+    #[command(flatten)]
+    config: ConfigArgs,
 }
 
-/// Stored value
-#[derive(Clone)]
-struct Value {
-    ts: u64,
-    data: Vec<u8>,
-}
+impl Configure for Args {
+    fn configure(&mut self, cfg: &mut Overlay) -> Result<()> {
+        cfg.set("bind", "bind", &mut self.bind)?;
+        cfg.set("workers", "workers", &mut self.workers)?;
+        cfg.set("shards", "shards", &mut self.shards)?;
+        self.limits.configure(cfg)?;
+        self.curve.configure(cfg)?;
+        self.auth.configure(cfg)?;
+        self.acl.configure(cfg)?;
+        self.quota.configure(cfg)?;
+        self.metrics.configure(cfg)?;
+        self.slowlog.configure(cfg)?;
+        self.monitor.configure(cfg)?;
+        self.hotkeys.configure(cfg)
+    }
 
-/// Very small (fast) hasher for sharding (XOR/shift)
-#[derive(Default)]
-struct FastHasher(u64);
-impl Hasher for FastHasher {
-    fn write(&mut self, bytes: &[u8]) {
-        // quick & dirty: xor-fold bytes
-        let mut h = self.0;
-        for &b in bytes {
-            h ^= b as u64;
-            h = h.rotate_left(5).wrapping_mul(0x9E3779B185EBCA87);
-        }
-        self.0 = h;
+    fn dump(&self, out: &mut Dump) {
+        out.put("bind", &self.bind);
+        out.put("workers", &self.workers);
+        out.put("shards", &self.shards);
+        self.limits.dump(out);
+        self.curve.dump(out);
+        self.auth.dump(out);
+        self.acl.dump(out);
+        self.quota.dump(out);
+        self.metrics.dump(out);
+        self.slowlog.dump(out);
+        self.monitor.dump(out);
+        self.hotkeys.dump(out);
     }
-    fn finish(&self) -> u64 {
-        self.0
+
+    fn validate(&self) -> Result<()> {
+        if self.workers == 0 {
+            bail!("workers (--workers): must be at least 1");
+        }
+        if self.shards == 0 {
+            bail!("shards (--shards): must be at least 1");
+        }
+        parse_binds(&self.bind)?;
+        self.limits.validate()?;
+        self.quota.validate()?;
+        self.monitor.validate()
     }
 }
-type FastBuild = BuildHasherDefault<FastHasher>;
 
-/// Sharded store: Vec<RwLock<HashMap>>
-struct ShardedStore {
-    shards: Vec<RwLock<HashMap<String, Value, FastBuild>>>,
-    mask: usize, // if power-of-two sized, we can mask. Otherwise use modulo.
-    pow2: bool,
-}
+/// Routing frames allowed ahead of a request: the client's routing id and the
+/// delimiter, with room for a couple of proxies in between.
+const MAX_ENVELOPE_FRAMES: usize = 4;
 
-impl ShardedStore {
-    fn new(n: usize) -> Self {
-        let cap = n.next_power_of_two();
-        let pow2 = cap == n;
-        let mask = if pow2 { n - 1 } else { 0 };
-        let mut shards = Vec::with_capacity(n);
-        for _ in 0..n {
-            shards.push(RwLock::new(HashMap::with_hasher(FastBuild::default())));
+/// Forward one request from the ROUTER to the workers, inserting the client
+/// identity and rate-limiting key as frames after the routing envelope (ZAP
+/// properties do not survive the hop over inproc).
+/// Returns false if the message was dropped as malformed or oversized.
+fn forward_request(frontend: &zmq::Socket, backend: &zmq::Socket, limits: &Limits) -> Result<bool> {
+    // Read the whole message before sending anything: a partly forwarded
+    // message would be completed by the next client's frames. Past the frame
+    // or byte limit the rest is drained without being kept.
+    let max_bytes = limits.max_msg_bytes();
+    let mut frames = Vec::new();
+    let (mut body_bytes, mut in_body, mut oversized) = (0usize, false, false);
+    loop {
+        let frame = frontend.recv_msg(0)?;
+        let more = frame.get_more();
+        if !oversized {
+            if in_body {
+                body_bytes = body_bytes.saturating_add(frame.len());
+            }
+            in_body |= frame.is_empty();
+            oversized = frames.len() == MAX_FRAMES + MAX_ENVELOPE_FRAMES || body_bytes > max_bytes;
+            if oversized {
+                frames.clear();
+            } else {
+                frames.push(frame);
+            }
         }
-        Self { shards, mask, pow2 }
-    }
-
-    #[inline]
-    fn shard_index(&self, key: &str) -> usize {
-        let mut h = FastHasher(0);
-        h.write(key.as_bytes());
-        let v = h.finish() as usize;
-        if self.pow2 {
-            v & self.mask
-        } else {
-            v % self.shards.len()
+        if !more {
+            break;
         }
     }
+    if oversized {
+        return Ok(false);
+    }
+    // Envelope: routing id(s) up to and including the empty delimiter, then a
+    // body. REQ clients never send anything else.
+    let Some(delimiter) = frames.iter().position(|f| f.is_empty()) else {
+        return Ok(false);
+    };
+    if delimiter == 0 || delimiter + 1 == frames.len() {
+        return Ok(false);
+    }
+    let mut body = frames.split_off(delimiter + 1);
+    let user = frame_user_id(&mut body[0]);
+    let client = match &user {
+        Some(id) => id.clone(),
+        None => match body[0].gets("Peer-Address") {
+            Some(addr) => format!("addr:{addr}"),
+            None => format!("peer:{}", hex::encode(&frames[0][..])),
+        },
+    };
+    for frame in frames {
+        backend.send(frame, zmq::SNDMORE)?;
+    }
+    backend.send(user.as_deref().unwrap_or(ANONYMOUS), zmq::SNDMORE)?;
+    backend.send(client.as_bytes(), zmq::SNDMORE)?;
+    let last = body.len() - 1;
+    for (i, frame) in body.into_iter().enumerate() {
+        backend.send(frame, if i < last { zmq::SNDMORE } else { 0 })?;
+    }
+    Ok(true)
+}
 
-    /// PUT semantics: replace only if new_ts >= old_ts. Returns Ok(true) if stored/updated,
-    /// Ok(false) if stale. Err on key encoding issues (shouldn't happen here).
-    fn put(&self, key: String, ts: u64, data: Vec<u8>) -> Result<bool> {
-        let idx = self.shard_index(&key);
-        let mut m = self.shards[idx]
-            .write()
-            .map_err(|_| anyhow!("store poisoned"))?;
-        match m.get(&key) {
-            Some(v) if ts < v.ts => Ok(false),
-            _ => {
-                m.insert(key, Value { ts, data });
-                Ok(true)
-            }
+/// Forward one reply unchanged.
+fn forward_reply(backend: &zmq::Socket, frontend: &zmq::Socket) -> Result<()> {
+    loop {
+        let frame = backend.recv_msg(0)?;
+        let more = frame.get_more();
+        frontend.send(frame, if more { zmq::SNDMORE } else { 0 })?;
+        if !more {
+            return Ok(());
         }
     }
+}
 
-    /// GET: None if miss.
-    fn get(&self, key: &str) -> Result<Option<Value>> {
-        let idx = self.shard_index(key);
-        let m = self.shards[idx]
-            .read()
-            .map_err(|_| anyhow!("store poisoned"))?;
-        Ok(m.get(key).cloned())
+/// ROUTER <-> DEALER proxy; blocks forever.
+fn proxy(frontend: &zmq::Socket, backend: &zmq::Socket, metrics: &Metrics, limits: &Limits) -> Result<()> {
+    loop {
+        let mut items = [frontend.as_poll_item(zmq::POLLIN), backend.as_poll_item(zmq::POLLIN)];
+        zmq::poll(&mut items, -1)?;
+        if items[0].is_readable() && forward_request(frontend, backend, limits)? {
+            metrics.enqueued();
+        }
+        if items[1].is_readable() {
+            forward_reply(backend, frontend)?;
+        }
     }
 }
 
 fn main() -> Result<()> {
-    let args = Args::parse();
+    let matches: ArgMatches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    args.log.init()?;
+    if !args.config.clone().resolve(&matches, &mut args)? {
+        return Ok(());
+    }
 
     let ctx = zmq::Context::new();
 
+    let mut server = Server::new(args.shards, args.limits);
+    server.acl = args.acl.load()?;
+    server.rate_limit = args.quota.rate_limiter();
+    server.prefix_quotas = args.quota.prefix_quotas()?;
+    server.namespaces.max = args.quota.max_namespaces;
+    server.slowlog = args.slowlog.slowlog();
+    server.hotkeys = args.hotkeys.hotkeys();
+
     // Frontend ROUTER for clients
     let frontend = ctx.socket(zmq::ROUTER)?;
-    frontend
-        .bind(&args.bind)
-        .with_context(|| format!("bind {}", &args.bind))?;
+    args.limits.apply(&frontend)?;
+    let listeners = Listeners::new(&ctx, &args.bind, args.monitor.monitor_bind.as_deref(), &args.curve, &args.auth)?;
+    let monitor = args.monitor.start(&ctx, &listeners)?;
+    let monitor_ep = monitor.as_ref().map(|(_, b)| b.endpoint.clone());
+    server.monitor = monitor.map(|(m, _)| m);
+    let server = Arc::new(server);
+    watch_connections(&ctx, &frontend, Arc::clone(&server))?;
+    let bound = listeners.bind(&frontend)?;
 
     // Backend DEALER for workers
     let backend = ctx.socket(zmq::DEALER)?;
     let backend_ep = "inproc://kvz-workers";
     backend.bind(backend_ep)?;
 
-    let store = Arc::new(ShardedStore::new(args.shards));
-    eprintln!(
-        "kvz-router listening on {} with {} workers, {} shards",
-        args.bind, args.workers, args.shards
-    );
+    info!(workers = args.workers, shards = args.shards, "kvz-router started");
+    for b in &bound {
+        b.log();
+    }
+    if let Some(addr) = args.metrics.start(Arc::clone(&server))? {
+        info!(url = %format_args!("http://{addr}/metrics"), "metrics listening");
+    }
+    if let Some(ep) = monitor_ep {
+        info!(endpoint = ep.as_str(), "monitor stream listening");
+    }
 
     // Spawn workers
     let mut handles = Vec::with_capacity(args.workers);
-    for _ in 0..args.workers {
+    for worker in 0..args.workers {
         let ctx_w = ctx.clone();
-        let store_w = Arc::clone(&store);
+        let server_w = Arc::clone(&server);
 
         handles.push(thread::spawn(move || -> Result<()> {
-            let rep = ctx_w.socket(zmq::REP)?;
+            let mut rep = ctx_w.socket(zmq::REP)?;
             rep.connect(backend_ep)?;
 
-            loop {
-                let msg = rep.recv_multipart(0)?;
-                if msg.is_empty() {
-                    send_err(&rep, "empty message")?;
-                    continue;
-                }
-                let cmd = std::str::from_utf8(&msg[0]).unwrap_or("");
-
-                match cmd {
-                    "PUT" => {
-                        if msg.len() != 4 {
-                            send_err(&rep, "PUT expects 4 frames")?;
-                            continue;
-                        }
-                        let key = match String::from_utf8(msg[1].clone()) {
-                            Ok(k) => k,
-                            Err(_) => {
-                                send_err(&rep, "key not utf-8")?;
-                                continue;
-                            }
-                        };
-                        if msg[2].len() != 8 {
-                            send_err(&rep, "timestamp must be 8 bytes (u64 BE)")?;
-                            continue;
-                        }
-                        let mut tsb = [0u8; 8];
-                        tsb.copy_from_slice(&msg[2]);
-                        let ts = u64::from_be_bytes(tsb);
-                        let data = msg[3].clone();
-
-                        match store_w.put(key, ts, data) {
-                            Ok(true) => rep.send_multipart(&[b"OK".as_slice()], 0)?,
-                            Ok(false) => rep.send_multipart(&[b"STALE".as_slice()], 0)?,
-                            Err(e) => send_err(&rep, &format!("store error: {e}"))?,
-                        }
-                    }
-                    "GET" => {
-                        if msg.len() != 2 {
-                            send_err(&rep, "GET expects 2 frames")?;
-                            continue;
-                        }
-                        let key = match String::from_utf8(msg[1].clone()) {
-                            Ok(k) => k,
-                            Err(_) => {
-                                send_err(&rep, "key not utf-8")?;
-                                continue;
-                            }
-                        };
-                        match store_w.get(&key) {
-                            Ok(Some(v)) => {
-                                let tsb = v.ts.to_be_bytes();
-                                rep.send_multipart(&[b"OK".as_slice(), &tsb, &v.data], 0)?;
-                            }
-                            Ok(None) => {
-                                rep.send_multipart(&[b"MISS".as_slice()], 0)?;
-                            }
-                            Err(e) => send_err(&rep, &format!("store error: {e}"))?,
-                        }
-                    }
-                    _ => {
-                        send_err(&rep, "unknown command")?;
-                    }
-                }
-            }
+            server_w.serve(&mut rep, worker, |sock, limits| {
+                let req = recv_tagged_request(sock, limits);
+                server_w.metrics.dequeued();
+                req
+            })
         }));
     }
 
-    // Start the built-in ZeroMQ proxy: ROUTER <-> DEALER
-    // This call blocks and forwards messages between frontend and backend.
+    // Forward messages between frontend and backend; this call blocks.
     // If you need a clean shutdown, handle a signal and close sockets.
-    zmq::proxy(&frontend, &backend).map_err(|e| anyhow!("proxy error: {e}"))?;
+    proxy(&frontend, &backend, &server.metrics, &args.limits).context("proxy error")?;
 
     // (Unreachable normally)
     for h in handles {
@@ -220,8 +266,3 @@ fn main() -> Result<()> {
     }
     Ok(())
 }
-
-fn send_err(sock: &zmq::Socket, msg: &str) -> Result<()> {
-    sock.send_multipart(&[b"ERR".as_slice(), msg.as_bytes()], 0)?;
-    Ok(())
-}
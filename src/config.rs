@@ -0,0 +1,427 @@
+//! Server configuration file (`--config`).
+//!
+//! A TOML file with the same settings as the server flags. Flags given on
+//! the command line override the file; the file overrides flag defaults.
+//!
+//! ```toml
+//! bind = ["tcp://*:5555", "ipc:///run/kvz.sock,curve=none"]  # or one string
+//! workers = 8             # kvz-router only, ignored by kvz server
+//! shards = 64             # kvz-router only, ignored by kvz server
+//!
+//! [limits]
+//! max_key_bytes = 4096
+//! max_value_bytes = 67108864
+//! max_msg_bytes = 67112000  # default: max_key_bytes + max_value_bytes + 64
+//! max_upload_bytes = 1073741824
+//!
+//! [security]
+//! curve_secret_key = "server.key_secret"
+//! allowlist = "allowlist.txt"
+//! acl = "acl.txt"
+//!
+//! [quota]
+//! client_ops_per_sec = 0.0
+//! client_bytes_per_sec = 0.0
+//! client_burst_secs = 1.0
+//! prefix_quota = ["tmp/=1048576"]
+//! max_namespaces = 1024
+//!
+//! [metrics]
+//! bind = "127.0.0.1:9100"
+//!
+//! [slowlog]
+//! threshold_us = 10000
+//! len = 128
+//!
+//! [monitor]
+//! bind = "tcp://127.0.0.1:5556"
+//! sample = 1.0
+//! value_bytes = 0
+//!
+//! [hotkeys]
+//! count = 16              # default 0, off
+//! decay_secs = 60
+//! ```
+//!
+//! The store is in memory only, so there is no persistence section. Unknown
+//! fields and values of the wrong type are errors naming the field.
+//! `--print-config` writes the effective settings in this format and exits.
+
+use crate::acl::AclArgs;
+use crate::auth::AuthArgs;
+use crate::curve::CurveServerArgs;
+use crate::hotkeys::HotKeysArgs;
+use crate::limits::{Limits, FRAME_SLACK};
+use crate::listen::parse_monitor_bind;
+use crate::metrics::MetricsArgs;
+use crate::monitor::MonitorArgs;
+use crate::quota::QuotaArgs;
+use crate::slowlog::SlowLogArgs;
+use anyhow::{anyhow, bail, Context, Result};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// A setting that can be read from and written to the config file.
+pub trait Setting: Sized {
+    /// What the file must hold, for error messages.
+    const EXPECTED: &'static str;
+    fn from_toml(value: &Value) -> Option<Self>;
+    /// None leaves the field out of the dump.
+    fn to_toml(&self) -> Option<Value>;
+}
+
+macro_rules! unsigned_setting {
+    ($($t:ty),*) => {$(
+        impl Setting for $t {
+            const EXPECTED: &'static str = "a non-negative integer";
+            fn from_toml(value: &Value) -> Option<Self> {
+                value.as_integer().and_then(|n| <$t>::try_from(n).ok())
+            }
+            fn to_toml(&self) -> Option<Value> {
+                i64::try_from(*self).ok().map(Value::Integer)
+            }
+        }
+    )*};
+}
+unsigned_setting!(usize, u64);
+
+impl Setting for f64 {
+    const EXPECTED: &'static str = "a number";
+    fn from_toml(value: &Value) -> Option<Self> {
+        value.as_float().or_else(|| value.as_integer().map(|n| n as f64))
+    }
+    fn to_toml(&self) -> Option<Value> {
+        Some(Value::Float(*self))
+    }
+}
+
+impl Setting for String {
+    const EXPECTED: &'static str = "a string";
+    fn from_toml(value: &Value) -> Option<Self> {
+        value.as_str().map(str::to_string)
+    }
+    fn to_toml(&self) -> Option<Value> {
+        Some(Value::String(self.clone()))
+    }
+}
+
+impl Setting for PathBuf {
+    const EXPECTED: &'static str = "a path";
+    fn from_toml(value: &Value) -> Option<Self> {
+        value.as_str().map(PathBuf::from)
+    }
+    fn to_toml(&self) -> Option<Value> {
+        Some(Value::String(self.display().to_string()))
+    }
+}
+
+impl Setting for Vec<String> {
+    const EXPECTED: &'static str = "a string or an array of strings";
+    fn from_toml(value: &Value) -> Option<Self> {
+        match value {
+            Value::String(s) => Some(vec![s.clone()]),
+            _ => value.as_array()?.iter().map(String::from_toml).collect(),
+        }
+    }
+    fn to_toml(&self) -> Option<Value> {
+        match self.as_slice() {
+            [one] => Some(Value::String(one.clone())),
+            list => Some(Value::Array(list.iter().cloned().map(Value::String).collect())),
+        }
+    }
+}
+
+impl<T: Setting> Setting for Option<T> {
+    const EXPECTED: &'static str = T::EXPECTED;
+    fn from_toml(value: &Value) -> Option<Self> {
+        T::from_toml(value).map(Some)
+    }
+    fn to_toml(&self) -> Option<Value> {
+        self.as_ref().and_then(T::to_toml)
+    }
+}
+
+/// A loaded config file, applied field by field to parsed flags.
+pub struct Overlay<'a> {
+    path: PathBuf,
+    /// Fields not yet applied; whatever is left at the end is unknown.
+    table: Table,
+    /// Sections some setting belongs to, so an empty one is not unknown.
+    sections: BTreeSet<String>,
+    matches: &'a ArgMatches,
+}
+
+impl Overlay<'_> {
+    /// Set `target` from the field `key` (`section.name` or `name`) unless
+    /// the flag with clap id `id` was given on the command line.
+    pub fn set<T: Setting>(&mut self, key: &str, id: &str, target: &mut T) -> Result<()> {
+        if let Some((section, _)) = key.split_once('.') {
+            self.sections.insert(section.to_string());
+        }
+        let value = match key.split_once('.') {
+            Some((section, name)) => match self.table.get_mut(section) {
+                Some(Value::Table(t)) => t.remove(name),
+                Some(_) => bail!("{}: {section}: expected a table", self.path.display()),
+                None => None,
+            },
+            None => self.table.remove(key),
+        };
+        let Some(value) = value else {
+            return Ok(());
+        };
+        let parsed = T::from_toml(&value)
+            .ok_or_else(|| anyhow!("{}: {key}: expected {}, got {value}", self.path.display(), T::EXPECTED))?;
+        if self.matches.value_source(id) != Some(ValueSource::CommandLine) {
+            *target = parsed;
+        }
+        Ok(())
+    }
+
+    /// Accept a top-level field without applying it, so one file can serve
+    /// both servers.
+    pub fn ignore(&mut self, key: &str) {
+        self.table.remove(key);
+    }
+
+    /// Fail on fields no setting consumed.
+    fn finish(self) -> Result<()> {
+        for (name, value) in &self.table {
+            match value {
+                Value::Table(t) => match t.keys().next() {
+                    Some(field) => bail!("{}: unknown field {name}.{field}", self.path.display()),
+                    None if !self.sections.contains(name) => bail!("{}: unknown field {name}", self.path.display()),
+                    None => {}
+                },
+                _ => bail!("{}: unknown field {name}", self.path.display()),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds the `--print-config` output.
+#[derive(Default)]
+pub struct Dump {
+    table: Table,
+}
+
+impl Dump {
+    pub fn put<T: Setting>(&mut self, key: &str, value: &T) {
+        let Some(value) = value.to_toml() else {
+            return;
+        };
+        match key.split_once('.') {
+            Some((section, name)) => {
+                let entry = self.table.entry(section).or_insert_with(|| Value::Table(Table::new()));
+                if let Value::Table(t) = entry {
+                    t.insert(name.to_string(), value);
+                }
+            }
+            None => {
+                self.table.insert(key.to_string(), value);
+            }
+        }
+    }
+}
+
+/// Options a config file can set.
+pub trait Configure {
+    /// Take the settings the file holds for flags not on the command line.
+    fn configure(&mut self, cfg: &mut Overlay) -> Result<()>;
+    /// Record the effective settings.
+    fn dump(&self, out: &mut Dump);
+    /// Check the effective settings; errors name the offending field.
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Configure for Limits {
+    fn configure(&mut self, cfg: &mut Overlay) -> Result<()> {
+        cfg.set("limits.max_key_bytes", "max_key_bytes", &mut self.max_key_bytes)?;
+        cfg.set("limits.max_value_bytes", "max_value_bytes", &mut self.max_value_bytes)?;
+        cfg.set("limits.max_msg_bytes", "max_msg_bytes", &mut self.max_msg_bytes)?;
+        cfg.set("limits.max_upload_bytes", "max_upload_bytes", &mut self.max_upload_bytes)
+    }
+
+    fn dump(&self, out: &mut Dump) {
+        out.put("limits.max_key_bytes", &self.max_key_bytes);
+        out.put("limits.max_value_bytes", &self.max_value_bytes);
+        out.put("limits.max_msg_bytes", &self.max_msg_bytes);
+        out.put("limits.max_upload_bytes", &self.max_upload_bytes);
+    }
+
+    fn validate(&self) -> Result<()> {
+        let at_least_one = [
+            ("limits.max_key_bytes (--max-key-bytes)", self.max_key_bytes),
+            ("limits.max_value_bytes (--max-value-bytes)", self.max_value_bytes),
+            ("limits.max_msg_bytes (--max-msg-bytes)", self.max_msg_bytes.unwrap_or(1)),
+            ("limits.max_upload_bytes (--max-upload-bytes)", self.max_upload_bytes),
+        ];
+        for (field, value) in at_least_one {
+            if value == 0 {
+                bail!("{field}: must be at least 1");
+            }
+        }
+        let default_msg = self.max_key_bytes.checked_add(self.max_value_bytes).and_then(|n| n.checked_add(FRAME_SLACK));
+        if self.max_msg_bytes.is_none() && default_msg.is_none() {
+            bail!("limits.max_key_bytes + limits.max_value_bytes: too large, set limits.max_msg_bytes (--max-msg-bytes)");
+        }
+        Ok(())
+    }
+}
+
+impl Configure for CurveServerArgs {
+    fn configure(&mut self, cfg: &mut Overlay) -> Result<()> {
+        cfg.set("security.curve_secret_key", "curve_secret_key", &mut self.curve_secret_key)
+    }
+
+    fn dump(&self, out: &mut Dump) {
+        out.put("security.curve_secret_key", &self.curve_secret_key);
+    }
+}
+
+impl Configure for AuthArgs {
+    fn configure(&mut self, cfg: &mut Overlay) -> Result<()> {
+        cfg.set("security.allowlist", "allowlist", &mut self.allowlist)
+    }
+
+    fn dump(&self, out: &mut Dump) {
+        out.put("security.allowlist", &self.allowlist);
+    }
+}
+
+impl Configure for AclArgs {
+    fn configure(&mut self, cfg: &mut Overlay) -> Result<()> {
+        cfg.set("security.acl", "acl", &mut self.acl)
+    }
+
+    fn dump(&self, out: &mut Dump) {
+        out.put("security.acl", &self.acl);
+    }
+}
+
+impl Configure for QuotaArgs {
+    fn configure(&mut self, cfg: &mut Overlay) -> Result<()> {
+        cfg.set("quota.client_ops_per_sec", "client_ops_per_sec", &mut self.client_ops_per_sec)?;
+        cfg.set("quota.client_bytes_per_sec", "client_bytes_per_sec", &mut self.client_bytes_per_sec)?;
+        cfg.set("quota.client_burst_secs", "client_burst_secs", &mut self.client_burst_secs)?;
+        cfg.set("quota.prefix_quota", "prefix_quotas", &mut self.prefix_quotas)?;
+        cfg.set("quota.max_namespaces", "max_namespaces", &mut self.max_namespaces)
+    }
+
+    fn dump(&self, out: &mut Dump) {
+        out.put("quota.client_ops_per_sec", &self.client_ops_per_sec);
+        out.put("quota.client_bytes_per_sec", &self.client_bytes_per_sec);
+        out.put("quota.client_burst_secs", &self.client_burst_secs);
+        out.put("quota.prefix_quota", &self.prefix_quotas);
+        out.put("quota.max_namespaces", &self.max_namespaces);
+    }
+
+    fn validate(&self) -> Result<()> {
+        self.prefix_quotas().context("quota.prefix_quota (--prefix-quota)")?;
+        if self.max_namespaces == 0 {
+            bail!("quota.max_namespaces (--max-namespaces): must be at least 1 (the default namespace)");
+        }
+        Ok(())
+    }
+}
+
+impl Configure for MetricsArgs {
+    fn configure(&mut self, cfg: &mut Overlay) -> Result<()> {
+        cfg.set("metrics.bind", "metrics_bind", &mut self.metrics_bind)
+    }
+
+    fn dump(&self, out: &mut Dump) {
+        out.put("metrics.bind", &self.metrics_bind);
+    }
+}
+
+impl Configure for SlowLogArgs {
+    fn configure(&mut self, cfg: &mut Overlay) -> Result<()> {
+        cfg.set("slowlog.threshold_us", "slowlog_threshold_us", &mut self.slowlog_threshold_us)?;
+        cfg.set("slowlog.len", "slowlog_len", &mut self.slowlog_len)
+    }
+
+    fn dump(&self, out: &mut Dump) {
+        out.put("slowlog.threshold_us", &self.slowlog_threshold_us);
+        out.put("slowlog.len", &self.slowlog_len);
+    }
+}
+
+impl Configure for MonitorArgs {
+    fn configure(&mut self, cfg: &mut Overlay) -> Result<()> {
+        cfg.set("monitor.bind", "monitor_bind", &mut self.monitor_bind)?;
+        cfg.set("monitor.sample", "monitor_sample", &mut self.monitor_sample)?;
+        cfg.set("monitor.value_bytes", "monitor_value_bytes", &mut self.monitor_value_bytes)
+    }
+
+    fn dump(&self, out: &mut Dump) {
+        out.put("monitor.bind", &self.monitor_bind);
+        out.put("monitor.sample", &self.monitor_sample);
+        out.put("monitor.value_bytes", &self.monitor_value_bytes);
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.monitor_sample) {
+            bail!("monitor.sample (--monitor-sample): must be between 0.0 and 1.0");
+        }
+        if let Some(spec) = &self.monitor_bind {
+            parse_monitor_bind(spec)?;
+        }
+        Ok(())
+    }
+}
+
+impl Configure for HotKeysArgs {
+    fn configure(&mut self, cfg: &mut Overlay) -> Result<()> {
+        cfg.set("hotkeys.count", "hotkeys", &mut self.hotkeys)?;
+        cfg.set("hotkeys.decay_secs", "hotkeys_decay_secs", &mut self.hotkeys_decay_secs)
+    }
+
+    fn dump(&self, out: &mut Dump) {
+        out.put("hotkeys.count", &self.hotkeys);
+        out.put("hotkeys.decay_secs", &self.hotkeys_decay_secs);
+    }
+}
+
+/// `--config`, `--print-config`.
+#[derive(Args, Debug, Clone, Default)]
+pub struct ConfigArgs {
+    /// Read settings from this TOML file; flags on the command line override it
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// Print the effective configuration as TOML and exit
+    #[arg(long)]
+    pub print_config: bool,
+}
+
+impl ConfigArgs {
+    /// Apply the config file, if any, to `args` (parsed from `matches`) and
+    /// validate the result. Returns false if `--print-config` printed it and
+    /// the server should not start.
+    pub fn resolve(&self, matches: &ArgMatches, args: &mut impl Configure) -> Result<bool> {
+        if let Some(path) = &self.config {
+            let mut cfg = Overlay { path: path.clone(), table: load(path)?, sections: BTreeSet::new(), matches };
+            args.configure(&mut cfg)?;
+            cfg.finish()?;
+        }
+        args.validate()?;
+        if self.print_config {
+            let mut out = Dump::default();
+            args.dump(&mut out);
+            print!("{}", out.table);
+            return Ok(false);
+        }
+        Ok(true)
+    }
+}
+
+fn load(path: &Path) -> Result<Table> {
+    let text = fs::read_to_string(path).with_context(|| format!("read config {}", path.display()))?;
+    text.parse::<Table>().map_err(|e| anyhow!("{}: {}", path.display(), e.to_string().trim_end()))
+}
@@ -0,0 +1,193 @@
+//! CURVE encryption/authentication options and key files.
+//!
+//! Key files use the ZeroMQ certificate layout (as written by czmq's `zcert`):
+//! `<name>` holds the public key, `<name>_secret` holds both keys. Keys are
+//! Z85-encoded, 40 characters each.
+
+use anyhow::{anyhow, Context, Result};
+use clap::Args;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A CURVE keypair (binary, 32 bytes each). `secret` is absent for public certificates.
+pub struct Cert {
+    pub public: Vec<u8>,
+    pub secret: Option<Vec<u8>>,
+}
+
+impl Cert {
+    pub fn generate() -> Result<Cert> {
+        ensure_curve()?;
+        let kp = zmq::CurveKeyPair::new()?;
+        Ok(Cert { public: kp.public_key.to_vec(), secret: Some(kp.secret_key.to_vec()) })
+    }
+
+    pub fn public_z85(&self) -> String {
+        z85(&self.public)
+    }
+
+    /// Read a certificate file (public or secret).
+    pub fn load(path: &Path) -> Result<Cert> {
+        let text = fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+        let mut public = None;
+        let mut secret = None;
+        for line in text.lines() {
+            let line = line.trim();
+            if line.starts_with('#') {
+                continue;
+            }
+            let Some((name, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim().trim_matches('"');
+            match name.trim() {
+                "public-key" => public = Some(decode_key(value)?),
+                "secret-key" => secret = Some(decode_key(value)?),
+                _ => {}
+            }
+        }
+        let public = public.ok_or_else(|| anyhow!("{}: no public-key", path.display()))?;
+        Ok(Cert { public, secret })
+    }
+
+    /// Write `<path>` (public) and `<path>_secret` (public + secret, mode 0600).
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let secret = self.secret.as_deref().ok_or_else(|| anyhow!("no secret key to save"))?;
+        let public_file = format!(
+            "#   ****  Generated by kvz keygen  ****\n\
+             #   ZeroMQ CURVE Public Certificate\n\
+             #   Exchange securely, or use a secure mechanism to verify the contents\n\
+             #   of this file after exchange. Store public certificates in your home\n\
+             #   directory, in the .curve subdirectory.\n\n\
+             metadata\n\
+             curve\n    public-key = \"{}\"\n",
+            z85(&self.public)
+        );
+        let secret_file = format!(
+            "#   ****  Generated by kvz keygen  ****\n\
+             #   ZeroMQ CURVE **Secret** Certificate\n\
+             #   DO NOT PROVIDE THIS FILE TO OTHER USERS nor change its permissions.\n\n\
+             metadata\n\
+             curve\n    public-key = \"{}\"\n    secret-key = \"{}\"\n",
+            z85(&self.public),
+            z85(secret)
+        );
+        let secret_path = secret_path(path);
+        fs::write(path, public_file).with_context(|| format!("write {}", path.display()))?;
+        write_private(&secret_path, secret_file.as_bytes())
+            .with_context(|| format!("write {}", secret_path.display()))?;
+        Ok(())
+    }
+}
+
+/// `<path>_secret`, the czmq naming convention for the secret half.
+pub fn secret_path(path: &Path) -> PathBuf {
+    let mut s = path.as_os_str().to_owned();
+    s.push("_secret");
+    PathBuf::from(s)
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    let mut f = fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?;
+    f.write_all(data)
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    fs::write(path, data)
+}
+
+fn z85(key: &[u8]) -> String {
+    zmq::z85_encode(key).expect("32-byte keys are always Z85-encodable")
+}
+
+fn decode_key(z85: &str) -> Result<Vec<u8>> {
+    let key = zmq::z85_decode(z85).map_err(|e| anyhow!("invalid Z85 key: {e}"))?;
+    if key.len() != 32 {
+        return Err(anyhow!("CURVE keys are 40 Z85 characters, got {}", z85.len()));
+    }
+    Ok(key)
+}
+
+fn ensure_curve() -> Result<()> {
+    if zmq::has("curve") == Some(true) {
+        Ok(())
+    } else {
+        Err(anyhow!("libzmq was built without CURVE support (it needs libsodium)"))
+    }
+}
+
+/// Server side: `--curve-secret-key`.
+#[derive(Args, Debug, Clone, Default)]
+pub struct CurveServerArgs {
+    /// Enable CURVE with this server secret certificate (from `kvz keygen`)
+    #[arg(long, value_name = "FILE")]
+    pub curve_secret_key: Option<PathBuf>,
+}
+
+impl CurveServerArgs {
+    /// Make `sock` a CURVE server. Must be called before bind. Returns the
+    /// server public key (Z85) for clients to use, or None if CURVE is off.
+    pub fn apply(&self, sock: &zmq::Socket) -> Result<Option<String>> {
+        curve_server(sock, self.curve_secret_key.as_deref())
+    }
+}
+
+/// Make the next endpoints `sock` binds CURVE servers with the secret
+/// certificate at `path`, or plain ones if None (libzmq captures security
+/// options at bind time). Returns the server public key (Z85).
+pub fn curve_server(sock: &zmq::Socket, path: Option<&Path>) -> Result<Option<String>> {
+    let Some(path) = path else {
+        // Without CURVE support the option is unknown, and already off.
+        if let Ok(true) = sock.is_curve_server() {
+            sock.set_curve_server(false)?;
+        }
+        return Ok(None);
+    };
+    ensure_curve()?;
+    let cert = Cert::load(path)?;
+    let secret = cert
+        .secret
+        .as_deref()
+        .ok_or_else(|| anyhow!("{} has no secret-key (pass the _secret file)", path.display()))?;
+    sock.set_curve_server(true)?;
+    sock.set_curve_secretkey(secret)?;
+    Ok(Some(cert.public_z85()))
+}
+
+/// Client side: `--curve-server-key` / `--curve-client-key`.
+#[derive(Args, Debug, Clone, Default)]
+pub struct CurveClientArgs {
+    /// Enable CURVE: server public key (Z85) or its public certificate file
+    #[arg(long, value_name = "KEY|FILE")]
+    pub curve_server_key: Option<String>,
+    /// Client secret certificate (default: a fresh ephemeral keypair)
+    #[arg(long, value_name = "FILE", requires = "curve_server_key")]
+    pub curve_client_key: Option<PathBuf>,
+}
+
+impl CurveClientArgs {
+    /// Make `sock` a CURVE client. Must be called before connect.
+    pub fn apply(&self, sock: &zmq::Socket) -> Result<()> {
+        let Some(server) = &self.curve_server_key else {
+            return Ok(());
+        };
+        ensure_curve()?;
+        let server_key = match decode_key(server) {
+            Ok(k) => k,
+            Err(_) => Cert::load(Path::new(server))?.public,
+        };
+        let client = match &self.curve_client_key {
+            Some(p) => Cert::load(p)?,
+            None => Cert::generate()?,
+        };
+        let secret = client.secret.as_deref().ok_or_else(|| anyhow!("client certificate has no secret-key"))?;
+        sock.set_curve_serverkey(&server_key)?;
+        sock.set_curve_publickey(&client.public)?;
+        sock.set_curve_secretkey(secret)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+//! Latency histograms in the style of HdrHistogram.
+//!
+//! Values (nanoseconds, up to `u64::MAX`) are counted in log-linear buckets:
+//! below 2^[`SUB_BITS`] every value has its own bucket, above that each
+//! power of two is split into 2^(SUB_BITS-1) equal buckets, so a value is
+//! known to within 1/1024 (better than three significant digits). A
+//! histogram is an array of counters, grown up to the largest bucket used,
+//! so recording is O(1) and histograms from several threads or runs merge by
+//! adding counts.
+//!
+//! Saved histograms are text, one line per non-empty bucket:
+//!
+//! ```text
+//! # kvz histogram v1
+//! count <total> min <ns> max <ns> sum <ns>
+//! <lowest value in bucket, ns> <count>
+//! ...
+//! ```
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+/// Bits of exact resolution; values below `1 << SUB_BITS` are exact.
+pub const SUB_BITS: u32 = 11;
+const SUB: usize = 1 << SUB_BITS;
+const HALF: usize = SUB / 2;
+const HEADER: &str = "# kvz histogram v1";
+
+#[derive(Clone)]
+pub struct Histogram {
+    /// Counts per bucket, up to the highest non-empty one.
+    counts: Vec<u64>,
+    total: u64,
+    min: u64,
+    max: u64,
+    sum: u128,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram::new()
+    }
+}
+
+fn index(v: u64) -> usize {
+    let bits = 64 - v.leading_zeros();
+    if bits <= SUB_BITS {
+        return v as usize;
+    }
+    // The top SUB_BITS bits of v, and how far they were shifted down.
+    let shift = bits - SUB_BITS;
+    shift as usize * HALF + (v >> shift) as usize
+}
+
+/// Lowest and highest value counted in bucket `i`.
+fn bounds(i: usize) -> (u64, u64) {
+    if i < SUB {
+        return (i as u64, i as u64);
+    }
+    let shift = (i / HALF - 1) as u32;
+    let top = (HALF + i % HALF) as u64;
+    let low = top << shift;
+    (low, low + ((1u64 << shift) - 1))
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        Histogram { counts: Vec::new(), total: 0, min: u64::MAX, max: 0, sum: 0 }
+    }
+
+    pub fn record(&mut self, v: u64) {
+        self.record_n(v, 1);
+    }
+
+    pub fn record_n(&mut self, v: u64, n: u64) {
+        if n == 0 {
+            return;
+        }
+        let i = index(v);
+        if i >= self.counts.len() {
+            self.counts.resize(i + 1, 0);
+        }
+        self.counts[i] += n;
+        self.total += n;
+        self.min = self.min.min(v);
+        self.max = self.max.max(v);
+        self.sum += v as u128 * n as u128;
+    }
+
+    /// Whether [`Histogram::merge`] with `other` would overflow the totals.
+    pub fn merge_overflows(&self, other: &Histogram) -> bool {
+        self.total.checked_add(other.total).is_none() || self.sum.checked_add(other.sum).is_none()
+    }
+
+    /// Add another histogram's counts to this one.
+    pub fn merge(&mut self, other: &Histogram) {
+        if other.counts.len() > self.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+        for (a, b) in self.counts.iter_mut().zip(&other.counts) {
+            *a += b;
+        }
+        self.total += other.total;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+    }
+
+    pub fn len(&self) -> u64 {
+        self.total
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    pub fn min(&self) -> u64 {
+        if self.is_empty() { 0 } else { self.min }
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.is_empty() { 0.0 } else { self.sum as f64 / self.total as f64 }
+    }
+
+    /// The value at quantile `q` (0.0–1.0): the highest value in the bucket
+    /// holding that rank, capped at the largest value recorded.
+    pub fn quantile(&self, q: f64) -> u64 {
+        if self.is_empty() {
+            return 0;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * self.total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, &c) in self.counts.iter().enumerate() {
+            seen += c;
+            if seen >= rank {
+                return bounds(i).1.min(self.max);
+            }
+        }
+        self.max
+    }
+
+    /// Non-empty buckets as `(lowest value, highest value, count)`.
+    pub fn buckets(&self) -> impl Iterator<Item = (u64, u64, u64)> + '_ {
+        self.counts.iter().enumerate().filter(|(_, c)| **c > 0).map(|(i, &c)| {
+            let (low, high) = bounds(i);
+            (low, high, c)
+        })
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = format!("{HEADER}\ncount {} min {} max {} sum {}\n", self.total, self.min(), self.max, self.sum);
+        for (low, _, c) in self.buckets() {
+            let _ = writeln!(out, "{low} {c}");
+        }
+        out
+    }
+
+    pub fn parse(text: &str) -> Result<Histogram> {
+        let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty());
+        if lines.next() != Some(HEADER) {
+            bail!("not a kvz histogram (expected {HEADER:?})");
+        }
+        let summary = lines.next().ok_or_else(|| anyhow!("missing summary line"))?;
+        let fields: Vec<&str> = summary.split_whitespace().collect();
+        let field = |name: &str| -> Result<&str> {
+            let i = fields.iter().position(|f| *f == name).ok_or_else(|| anyhow!("missing {name}"))?;
+            fields.get(i + 1).copied().ok_or_else(|| anyhow!("missing value for {name}"))
+        };
+        let mut h = Histogram::new();
+        for (n, line) in lines.enumerate() {
+            let (v, c) = line.split_once(' ').ok_or_else(|| anyhow!("line {}: expected <value> <count>", n + 3))?;
+            let v: u64 = v.trim().parse().with_context(|| format!("line {}: bad value", n + 3))?;
+            let c: u64 = c.trim().parse().with_context(|| format!("line {}: bad count", n + 3))?;
+            // Every bucket count is at most the total, so checking it covers them too.
+            if h.total.checked_add(c).is_none() || h.sum.checked_add(v as u128 * c as u128).is_none() {
+                bail!("line {}: counts overflow", n + 3);
+            }
+            h.record_n(v, c);
+        }
+        // Bucket values are lower bounds; restore the exact extremes and sum.
+        let total: u64 = field("count")?.parse().context("bad count")?;
+        if total != h.total {
+            bail!("count {total} does not match the buckets ({})", h.total);
+        }
+        if total > 0 {
+            h.min = field("min")?.parse().context("bad min")?;
+            h.max = field("max")?.parse().context("bad max")?;
+            h.sum = field("sum")?.parse().context("bad sum")?;
+        }
+        Ok(h)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_text()).with_context(|| format!("write {}", path.display()))
+    }
+
+    pub fn load(path: &Path) -> Result<Histogram> {
+        let text = fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+        Histogram::parse(&text).with_context(|| format!("parse {}", path.display()))
+    }
+}
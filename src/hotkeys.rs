@@ -0,0 +1,357 @@
+//! Approximate hot-key statistics (`HOTKEYS`, `kvz hotkeys`).
+//!
+//! Reads and writes per key are counted in count-min sketches: a few rows of
+//! atomic counters indexed by independent hashes, where a key's estimate is
+//! its smallest counter. Estimates never undercount and overcount by at most
+//! a small fraction of all traffic. Next to each sketch a top-K list keeps
+//! the keys with the highest estimates. A key is only offered to its list
+//! when its estimate reaches a milestone (every count up to 16, then eight
+//! steps per doubling) and beats the current K-th entry, so ordinary
+//! requests, and even a hot key hit on every request, mostly cost one hash
+//! and a few atomic adds. A third list keeps the largest values written.
+//!
+//! All counts are halved every `--hotkeys-decay-secs`, so the lists follow
+//! recent traffic rather than all-time totals.
+//!
+//! Tracking is off unless `--hotkeys K` is given: even cheap counting adds
+//! shared-counter traffic on the hottest keys, so it is paid for only when
+//! someone wants the answer. Keys are hashed with the store's
+//! [`FastHasher`], mixed once so every bit feeds the sketch columns.
+
+use crate::store::FastHasher;
+use clap::Args;
+use std::cmp::Reverse;
+use std::hash::Hasher;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Counters per sketch row.
+const WIDTH: usize = 4096;
+/// Sketch rows (independent hashes).
+const DEPTH: usize = 4;
+/// Bits in a list's membership filter.
+const LISTED_BITS: usize = 1024;
+/// Default `--hotkeys`: off.
+const DEFAULT_K: usize = 0;
+/// Default `--hotkeys-decay-secs`.
+const DEFAULT_DECAY_SECS: u64 = 60;
+
+/// Whether an estimate of `n` is worth offering to a top-K list: every
+/// count below 16, then every 1/8 of the enclosing power of two.
+fn milestone(n: u64) -> bool {
+    let step = (1u64 << n.max(1).ilog2()) / 8;
+    step <= 1 || n.is_multiple_of(step)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotKind {
+    Reads,
+    Writes,
+    /// Largest values, by size in bytes.
+    Largest,
+}
+
+impl HotKind {
+    pub fn parse(s: &str) -> Option<HotKind> {
+        match s.to_ascii_lowercase().as_str() {
+            "reads" => Some(HotKind::Reads),
+            "writes" => Some(HotKind::Writes),
+            "largest" => Some(HotKind::Largest),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct HotKey {
+    pub namespace: String,
+    pub key: Vec<u8>,
+    /// Estimated accesses, or value size for [`HotKind::Largest`].
+    pub count: u64,
+}
+
+struct Sketch {
+    counters: Vec<AtomicU64>,
+}
+
+impl Sketch {
+    fn new() -> Sketch {
+        Sketch { counters: (0..WIDTH * DEPTH).map(|_| AtomicU64::new(0)).collect() }
+    }
+
+    /// Count one occurrence of the key hashed to `h`; returns its estimate.
+    fn add(&self, h: u64) -> u64 {
+        // Row hashes by double hashing: h1 + i * h2.
+        let (h1, h2) = (h as u32 as usize, ((h >> 32) as usize) | 1);
+        (0..DEPTH)
+            .map(|i| {
+                let col = h1.wrapping_add(i.wrapping_mul(h2)) % WIDTH;
+                self.counters[i * WIDTH + col].fetch_add(1, Ordering::Relaxed) + 1
+            })
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn halve(&self) {
+        for c in &self.counters {
+            // Racing increments may be lost; the sketch is approximate anyway.
+            c.store(c.load(Ordering::Relaxed) / 2, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A listed key and its hash, which indexes the membership filter.
+struct Entry {
+    hash: u64,
+    key: HotKey,
+}
+
+/// The `k` keys with the highest counts offered so far.
+struct TopK {
+    k: usize,
+    entries: Mutex<Vec<Entry>>,
+    /// Lowest count in a full list; offers at or below it are ignored
+    /// without locking.
+    floor: AtomicU64,
+    /// One bit per `hash % LISTED_BITS` of the listed keys, so offers for
+    /// keys already listed get through the floor check without locking.
+    listed: Vec<AtomicU64>,
+}
+
+impl TopK {
+    fn new(k: usize) -> TopK {
+        TopK {
+            k,
+            entries: Mutex::new(Vec::with_capacity(k)),
+            floor: AtomicU64::new(0),
+            listed: (0..LISTED_BITS / 64).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    fn beats_floor(&self, count: u64) -> bool {
+        count > self.floor.load(Ordering::Relaxed)
+    }
+
+    /// Whether the key hashed to `h` may be listed; false positives only
+    /// cost a lock.
+    fn maybe_listed(&self, h: u64) -> bool {
+        let bit = h as usize % LISTED_BITS;
+        self.listed[bit / 64].load(Ordering::Relaxed) & (1 << (bit % 64)) != 0
+    }
+
+    /// Whether an offer of `count` for the key hashed to `h` could change
+    /// the list.
+    fn wants(&self, h: u64, count: u64) -> bool {
+        self.k > 0 && (self.beats_floor(count) || self.maybe_listed(h))
+    }
+
+    /// Record `count` as the key's current count. A listed key takes the
+    /// new count even when it is lower.
+    fn offer(&self, h: u64, namespace: &str, key: &[u8], count: u64) {
+        if !self.wants(h, count) {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let full = entries.len() == self.k;
+        let entry = || Entry { hash: h, key: HotKey { namespace: namespace.to_string(), key: key.to_vec(), count } };
+        match entries.iter_mut().find(|e| e.hash == h && e.key.key == key && e.key.namespace == namespace) {
+            Some(e) if e.key.count == count => return,
+            Some(e) => e.key.count = count,
+            None if !self.beats_floor(count) => return,
+            None if !full => entries.push(entry()),
+            None => {
+                let (i, min) = entries.iter().enumerate().min_by_key(|(_, e)| e.key.count).expect("list is full");
+                if count <= min.key.count {
+                    return;
+                }
+                entries[i] = entry();
+            }
+        }
+        self.refresh(&entries);
+    }
+
+    /// Recompute the floor and membership filter after the list changed.
+    fn refresh(&self, entries: &[Entry]) {
+        let floor = if entries.len() == self.k { entries.iter().map(|e| e.key.count).min().unwrap_or(0) } else { 0 };
+        self.floor.store(floor, Ordering::Relaxed);
+        let mut words = [0u64; LISTED_BITS / 64];
+        for e in entries {
+            let bit = e.hash as usize % LISTED_BITS;
+            words[bit / 64] |= 1 << (bit % 64);
+        }
+        for (w, bits) in self.listed.iter().zip(words) {
+            w.store(bits, Ordering::Relaxed);
+        }
+    }
+
+    fn halve(&self) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        for e in entries.iter_mut() {
+            e.key.count /= 2;
+        }
+        entries.retain(|e| e.key.count > 0);
+        self.refresh(&entries);
+    }
+
+    fn forget(&self, namespace: &str) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.retain(|e| e.key.namespace != namespace);
+        self.refresh(&entries);
+    }
+
+    fn sorted(&self) -> Vec<HotKey> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let mut keys: Vec<HotKey> = entries.iter().map(|e| e.key.clone()).collect();
+        keys.sort_by_key(|e| Reverse(e.count));
+        keys
+    }
+}
+
+pub struct HotKeys {
+    reads: Sketch,
+    writes: Sketch,
+    top_reads: TopK,
+    top_writes: TopK,
+    largest: TopK,
+    started: Instant,
+    /// Halve all counts this often; zero never.
+    decay: Duration,
+    /// Milliseconds since `started` of the next halving.
+    next_decay_ms: AtomicU64,
+}
+
+impl Default for HotKeys {
+    fn default() -> Self {
+        HotKeysArgs::default().hotkeys()
+    }
+}
+
+impl HotKeys {
+    pub fn new(k: usize, decay: Duration) -> HotKeys {
+        // Disabled tracking needs no counters.
+        let sketch = || if k == 0 { Sketch { counters: Vec::new() } } else { Sketch::new() };
+        HotKeys {
+            reads: sketch(),
+            writes: sketch(),
+            top_reads: TopK::new(k),
+            top_writes: TopK::new(k),
+            largest: TopK::new(k),
+            started: Instant::now(),
+            decay,
+            next_decay_ms: AtomicU64::new(decay.as_millis() as u64),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.top_reads.k > 0
+    }
+
+    fn hash(&self, namespace: &str, key: &[u8]) -> u64 {
+        let mut h = FastHasher::default();
+        h.write_usize(namespace.len());
+        h.write(namespace.as_bytes());
+        h.write(key);
+        // The sketch columns come from the low bits of each half, which
+        // FastHasher alone leaves weakly mixed (MurmurHash3 finalizer).
+        let mut x = h.finish();
+        x ^= x >> 33;
+        x = x.wrapping_mul(0xff51afd7ed558ccd);
+        x ^= x >> 33;
+        x = x.wrapping_mul(0xc4ceb9fe1a85ec53);
+        x ^ (x >> 33)
+    }
+
+    pub fn read(&self, namespace: &str, key: &[u8]) {
+        if self.enabled() {
+            self.maybe_decay();
+            let h = self.hash(namespace, key);
+            let n = self.reads.add(h);
+            if milestone(n) {
+                self.top_reads.offer(h, namespace, key, n);
+            }
+        }
+    }
+
+    pub fn write(&self, namespace: &str, key: &[u8]) {
+        if self.enabled() {
+            self.maybe_decay();
+            let h = self.hash(namespace, key);
+            let n = self.writes.add(h);
+            if milestone(n) {
+                self.top_writes.offer(h, namespace, key, n);
+            }
+        }
+    }
+
+    /// Whether a value of `size` bytes for `key` would make or change the
+    /// largest-values list, so callers can skip copying the key otherwise.
+    pub fn wants_size(&self, namespace: &str, key: &[u8], size: usize) -> bool {
+        self.enabled() && self.largest.wants(self.hash(namespace, key), size as u64)
+    }
+
+    /// Record the size of a value just stored, replacing the listed size of
+    /// an overwritten key even when it shrank.
+    pub fn stored(&self, namespace: &str, key: &[u8], size: usize) {
+        if self.enabled() {
+            self.largest.offer(self.hash(namespace, key), namespace, key, size as u64);
+        }
+    }
+
+    /// Drop the listed keys of a flushed or dropped namespace. Its sketch
+    /// counts fade with the decay.
+    pub fn forget(&self, namespace: &str) {
+        for list in [&self.top_reads, &self.top_writes, &self.largest] {
+            list.forget(namespace);
+        }
+    }
+
+    /// The tracked keys of one kind, highest count first.
+    pub fn top(&self, kind: HotKind) -> Vec<HotKey> {
+        match kind {
+            HotKind::Reads => self.top_reads.sorted(),
+            HotKind::Writes => self.top_writes.sorted(),
+            HotKind::Largest => self.largest.sorted(),
+        }
+    }
+
+    fn maybe_decay(&self) {
+        if self.decay.is_zero() {
+            return;
+        }
+        let now = self.started.elapsed().as_millis() as u64;
+        let due = self.next_decay_ms.load(Ordering::Relaxed);
+        let next = now + self.decay.as_millis() as u64;
+        // Only the thread that moves the deadline does the halving.
+        if now < due || self.next_decay_ms.compare_exchange(due, next, Ordering::Relaxed, Ordering::Relaxed).is_err() {
+            return;
+        }
+        self.reads.halve();
+        self.writes.halve();
+        self.top_reads.halve();
+        self.top_writes.halve();
+    }
+}
+
+/// `--hotkeys`, `--hotkeys-decay-secs`.
+#[derive(Args, Debug, Clone)]
+pub struct HotKeysArgs {
+    /// Track this many hottest keys by reads and writes, and largest values (0 = off, the default)
+    #[arg(long, value_name = "K", default_value_t = DEFAULT_K)]
+    pub hotkeys: usize,
+    /// Halve hot-key counts this often, so they follow recent traffic (0 = never)
+    #[arg(long, value_name = "SECS", default_value_t = DEFAULT_DECAY_SECS)]
+    pub hotkeys_decay_secs: u64,
+}
+
+impl Default for HotKeysArgs {
+    fn default() -> Self {
+        HotKeysArgs { hotkeys: DEFAULT_K, hotkeys_decay_secs: DEFAULT_DECAY_SECS }
+    }
+}
+
+impl HotKeysArgs {
+    pub fn hotkeys(&self) -> HotKeys {
+        HotKeys::new(self.hotkeys, Duration::from_secs(self.hotkeys_decay_secs))
+    }
+}
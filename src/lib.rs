@@ -0,0 +1,21 @@
+//! Shared pieces of the kvz servers, CLI client and benchmark.
+
+pub mod acl;
+pub mod auth;
+pub mod config;
+pub mod curve;
+pub mod histogram;
+pub mod hotkeys;
+pub mod limits;
+pub mod listen;
+pub mod logging;
+pub mod metrics;
+pub mod monitor;
+pub mod namespace;
+pub mod payload;
+pub mod protocol;
+pub mod quota;
+pub mod server;
+pub mod session;
+pub mod slowlog;
+pub mod store;
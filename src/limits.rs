@@ -0,0 +1,63 @@
+//! Request size limits shared by both servers.
+
+use crate::protocol::{ErrorCode, KvzError};
+use anyhow::Result;
+use clap::Args;
+
+/// Framing overhead allowed on top of key + value when `--max-msg-bytes` is not given
+pub const FRAME_SLACK: usize = 64;
+
+/// Size limits, advertised to clients via the `LIMITS` command.
+#[derive(Args, Debug, Clone, Copy)]
+pub struct Limits {
+    /// Maximum key length in bytes
+    #[arg(long, default_value_t = 4096)]
+    pub max_key_bytes: usize,
+    /// Maximum value size in bytes
+    #[arg(long, default_value_t = 64 * 1024 * 1024)]
+    pub max_value_bytes: usize,
+    /// Maximum total request size in bytes, also applied as ZMQ_MAXMSGSIZE
+    /// [default: max-key-bytes + max-value-bytes + 64]
+    #[arg(long)]
+    pub max_msg_bytes: Option<usize>,
+    /// Maximum bytes buffered by all chunked uploads together, awaiting
+    /// PUTCOMMIT
+    #[arg(long, default_value_t = 1 << 30)]
+    pub max_upload_bytes: usize,
+}
+
+impl Limits {
+    pub fn max_msg_bytes(&self) -> usize {
+        self.max_msg_bytes.unwrap_or_else(|| {
+            self.max_key_bytes.saturating_add(self.max_value_bytes).saturating_add(FRAME_SLACK)
+        })
+    }
+
+    /// Set ZMQ_MAXMSGSIZE so oversized frames are dropped (and the peer disconnected)
+    /// before we ever allocate them. Must be called before bind.
+    pub fn apply(&self, sock: &zmq::Socket) -> Result<()> {
+        let max = i64::try_from(self.max_msg_bytes()).unwrap_or(i64::MAX);
+        sock.set_maxmsgsize(max)?;
+        Ok(())
+    }
+
+    pub fn check_key(&self, key: &[u8]) -> Result<(), KvzError> {
+        if key.len() > self.max_key_bytes {
+            return Err(KvzError::new(
+                ErrorCode::KeyTooLarge,
+                format!("key is {} bytes, limit is {}", key.len(), self.max_key_bytes),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn check_value(&self, len: usize) -> Result<(), KvzError> {
+        if len > self.max_value_bytes {
+            return Err(KvzError::new(
+                ErrorCode::ValueTooLarge,
+                format!("value is {len} bytes, limit is {}", self.max_value_bytes),
+            ));
+        }
+        Ok(())
+    }
+}
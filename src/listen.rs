@@ -0,0 +1,209 @@
+//! Server endpoints: `--bind`, repeatable, each with optional security.
+//!
+//! ```text
+//! --bind ENDPOINT[,curve=FILE|none][,allowlist=FILE|none]
+//! ```
+//!
+//! An endpoint uses `--curve-secret-key` and `--allowlist` unless it names
+//! its own certificate or allowlist, or turns them off with `none`. For
+//! example a router can serve local clients in the clear and remote ones
+//! over CURVE:
+//!
+//! ```text
+//! kvz-router --bind ipc:///run/kvz.sock \
+//!            --bind 'tcp://*:5555,curve=server.key_secret,allowlist=remote.txt'
+//! ```
+//!
+//! libzmq captures security options when an endpoint is bound, so the
+//! settings are applied to the socket before each bind. Every endpoint with
+//! its own allowlist gets a ZAP domain of its own; endpoints without one
+//! that follow it get [`OPEN_ZAP_DOMAIN`], since a domain cannot be cleared.
+//!
+//! `--monitor-bind` takes the same options. Since the monitor stream carries
+//! every client's requests, it is refused when any endpoint has an allowlist
+//! but the stream would have none; `allowlist=none` opts out explicitly.
+
+use crate::auth::{start_zap, AuthArgs, OPEN_ZAP_DOMAIN, ZAP_DOMAIN};
+use crate::curve::{curve_server, CurveServerArgs};
+use anyhow::{anyhow, bail, Context, Result};
+use std::path::PathBuf;
+use tracing::info;
+
+/// A per-endpoint override: None inherits the server-wide setting,
+/// `Some(None)` turns it off.
+type Override = Option<Option<PathBuf>>;
+
+/// One `--bind` value.
+#[derive(Debug, Clone)]
+pub struct Bind {
+    pub endpoint: String,
+    curve: Override,
+    allowlist: Override,
+}
+
+impl Bind {
+    pub fn parse(spec: &str) -> Result<Bind> {
+        let mut parts = spec.split(',');
+        let endpoint = parts.next().unwrap_or_default().trim();
+        if endpoint.is_empty() {
+            bail!("missing endpoint in {spec:?}");
+        }
+        let mut bind = Bind { endpoint: endpoint.to_string(), curve: None, allowlist: None };
+        for opt in parts {
+            let (name, value) = opt
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expected name=value, got {opt:?} in {spec:?}"))?;
+            let value = match value.trim() {
+                "" => bail!("empty {name} in {spec:?}"),
+                "none" => None,
+                file => Some(PathBuf::from(file)),
+            };
+            match name.trim() {
+                "curve" => bind.curve = Some(value),
+                "allowlist" => bind.allowlist = Some(value),
+                other => bail!("unknown bind option {other:?} in {spec:?} (expected curve or allowlist)"),
+            }
+        }
+        Ok(bind)
+    }
+}
+
+/// Parse `--bind` values; errors name the option.
+pub fn parse_binds(specs: &[String]) -> Result<Vec<Bind>> {
+    if specs.is_empty() {
+        bail!("bind (--bind): at least one endpoint is required");
+    }
+    specs.iter().map(|s| Bind::parse(s).context("bind (--bind)")).collect()
+}
+
+/// An endpoint the server listens on.
+pub struct Bound {
+    /// As bound, with wildcard addresses and ephemeral ports resolved.
+    pub endpoint: String,
+    /// Server public key (Z85) if the endpoint uses CURVE.
+    pub curve_key: Option<String>,
+    pub allowlist: Option<PathBuf>,
+}
+
+impl Bound {
+    /// Report the endpoint at startup.
+    pub fn log(&self) {
+        let allowlist = self.allowlist.as_ref().map(|p| p.display().to_string());
+        let allowlist = allowlist.as_deref().unwrap_or("none");
+        match &self.curve_key {
+            Some(key) => info!(endpoint = self.endpoint.as_str(), public_key = key.as_str(), allowlist, "listening"),
+            None => info!(endpoint = self.endpoint.as_str(), curve = "none", allowlist, "listening"),
+        }
+    }
+}
+
+/// An endpoint with its effective security.
+struct Endpoint {
+    bind: Bind,
+    cert: Option<PathBuf>,
+    /// ZAP domain and allowlist file.
+    zap: Option<(String, PathBuf)>,
+}
+
+impl Endpoint {
+    /// Resolve `bind` against the server-wide flags; an allowlist of its own
+    /// is served under `domain` and added to `allowlists`.
+    fn resolve(
+        bind: Bind,
+        domain: String,
+        curve: &CurveServerArgs,
+        auth: &AuthArgs,
+        allowlists: &mut Vec<(String, PathBuf)>,
+    ) -> Endpoint {
+        let cert = bind.curve.clone().unwrap_or_else(|| curve.curve_secret_key.clone());
+        let zap = match &bind.allowlist {
+            None => auth.allowlist.clone().map(|path| (ZAP_DOMAIN.to_string(), path)),
+            Some(None) => None,
+            Some(Some(path)) => {
+                allowlists.push((domain.clone(), path.clone()));
+                Some((domain, path.clone()))
+            }
+        };
+        Endpoint { bind, cert, zap }
+    }
+
+    /// Bind `sock` to this endpoint; `domain` is the socket's current ZAP
+    /// domain and is updated.
+    fn bind<'a>(&'a self, sock: &zmq::Socket, domain: &mut &'a str) -> Result<Bound> {
+        let Endpoint { bind, cert, zap } = self;
+        let curve_key = curve_server(sock, cert.as_deref()).with_context(|| format!("bind {}", bind.endpoint))?;
+        let next = match zap {
+            Some((d, _)) => d.as_str(),
+            None if domain.is_empty() => "",
+            None => OPEN_ZAP_DOMAIN,
+        };
+        if next != *domain {
+            sock.set_zap_domain(next).context("set ZAP domain")?;
+            *domain = next;
+        }
+        sock.bind(&bind.endpoint).with_context(|| format!("bind {}", bind.endpoint))?;
+        let endpoint = sock.get_last_endpoint()?.unwrap_or_else(|_| bind.endpoint.clone());
+        Ok(Bound { endpoint, curve_key, allowlist: zap.as_ref().map(|(_, path)| path.clone()) })
+    }
+}
+
+/// The server's endpoints.
+pub struct Listeners {
+    binds: Vec<Endpoint>,
+    monitor: Option<Endpoint>,
+}
+
+impl Listeners {
+    /// Resolve per-endpoint security, for the request endpoints and the
+    /// `--monitor-bind` one, against the server-wide flags and start the ZAP
+    /// handler for all allowlists in use.
+    pub fn new(
+        ctx: &zmq::Context,
+        specs: &[String],
+        monitor: Option<&str>,
+        curve: &CurveServerArgs,
+        auth: &AuthArgs,
+    ) -> Result<Listeners> {
+        let mut allowlists = Vec::new();
+        if let Some(path) = &auth.allowlist {
+            allowlists.push((ZAP_DOMAIN.to_string(), path.clone()));
+        }
+        let mut binds = Vec::new();
+        for (i, bind) in parse_binds(specs)?.into_iter().enumerate() {
+            binds.push(Endpoint::resolve(bind, format!("{ZAP_DOMAIN}.{i}"), curve, auth, &mut allowlists));
+        }
+        let monitor = match monitor {
+            Some(spec) => {
+                let bind = parse_monitor_bind(spec)?;
+                let explicit = bind.allowlist.is_some();
+                let ep = Endpoint::resolve(bind, format!("{ZAP_DOMAIN}.monitor"), curve, auth, &mut allowlists);
+                if ep.zap.is_none() && !explicit && binds.iter().any(|b| b.zap.is_some()) {
+                    bail!(
+                        "monitor.bind (--monitor-bind): the request endpoints use allowlists but the monitor \
+                         stream would have none; add allowlist=FILE, or allowlist=none to serve it unauthenticated"
+                    );
+                }
+                Some(ep)
+            }
+            None => None,
+        };
+        start_zap(ctx, allowlists)?;
+        Ok(Listeners { binds, monitor })
+    }
+
+    /// Bind `sock` to every request endpoint with its security settings.
+    pub fn bind(&self, sock: &zmq::Socket) -> Result<Vec<Bound>> {
+        let mut domain = "";
+        self.binds.iter().map(|ep| ep.bind(sock, &mut domain)).collect()
+    }
+
+    /// Bind `sock` to the `--monitor-bind` endpoint, if any.
+    pub fn bind_monitor(&self, sock: &zmq::Socket) -> Result<Option<Bound>> {
+        self.monitor.as_ref().map(|ep| ep.bind(sock, &mut "")).transpose()
+    }
+}
+
+/// Parse the `--monitor-bind` value; errors name the option.
+pub fn parse_monitor_bind(spec: &str) -> Result<Bind> {
+    Bind::parse(spec).context("monitor.bind (--monitor-bind)")
+}
@@ -0,0 +1,150 @@
+//! Structured logging and request spans for all binaries, on top of
+//! `tracing`.
+//!
+//! Events go to stderr as text (`<time> <LEVEL> <spans>: <target>: <message>
+//! k=v ...`) or as one JSON object per line (`--log-format json`), with the
+//! fields of the event flattened into the object. The format falls back to
+//! `$KVZ_LOG_FORMAT`.
+//!
+//! The filter (`--log-level`, else `$KVZ_LOG`, else `info`) uses `EnvFilter`
+//! syntax: a default level optionally followed by per-target levels, the
+//! most specific winning, e.g. `warn,kvz::auth=debug`. Targets match at
+//! `::` boundaries, so `kvz=debug` does not enable `kvz_bench`.
+//!
+//! Each request a worker handles runs in a `request` span (fields `req`,
+//! `worker`, `command`, `reply`, `lock_wait_us`) with `parse`, `handle` and
+//! `reply` spans inside, all under the [`SPAN_TARGET`] target at `trace`
+//! level. A span is logged with its busy and idle time when it closes;
+//! enable them with e.g. `--log-level info,kvz::span=trace`.
+
+use anyhow::{anyhow, Result};
+use clap::{Args, ValueEnum};
+use std::io::IsTerminal;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
+
+/// Environment variable with the default log filter.
+pub const LOG_ENV: &str = "KVZ_LOG";
+/// Environment variable with the default log format.
+pub const LOG_FORMAT_ENV: &str = "KVZ_LOG_FORMAT";
+/// Target of request spans.
+pub const SPAN_TARGET: &str = "kvz::span";
+/// Crate-level targets of this package's library and binaries.
+const CRATES: [&str; 3] = ["kvz", "kvz_bench", "kvz_router"];
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// The `EnvFilter` for `spec`, defaulting to `info`.
+///
+/// `EnvFilter` matches targets by plain prefix, so `kvz=debug` alone would
+/// also cover `kvz_bench`. Each crate of this package that another
+/// directive's target is a bare prefix of gets a directive of its own at the
+/// default level, which wins as the more specific one.
+fn env_filter(spec: &str) -> Result<EnvFilter> {
+    let mut directives: Vec<&str> = spec.split(',').map(str::trim).filter(|d| !d.is_empty()).collect();
+    let mut default = None;
+    let mut targets = Vec::new();
+    for d in &directives {
+        match d.split_once('=') {
+            // Span filters (`target[span{field}]=level`) are left to EnvFilter.
+            _ if d.contains('[') => {}
+            Some((target, _)) => targets.push(target),
+            None if LevelFilter::from_str(d).is_ok() => default = Some(*d),
+            None => targets.push(d),
+        }
+    }
+    let default = default.unwrap_or_else(|| {
+        directives.insert(0, "info");
+        "info"
+    });
+    let mut spec = directives.join(",");
+    for krate in CRATES {
+        if !targets.contains(&krate) && targets.iter().any(|t| krate.starts_with(t)) {
+            spec.push_str(&format!(",{krate}={default}"));
+        }
+    }
+    EnvFilter::builder().parse(&spec).map_err(|e| anyhow!("{e}"))
+}
+
+/// `YYYY-MM-DD HH:MM:SS.mmm` (UTC) for a Unix time in milliseconds.
+pub fn utc_time(ms: u64) -> String {
+    let secs = ms / 1000;
+    let days = (secs / 86_400) as i64;
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    let tod = secs % 86_400;
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}.{:03}",
+        tod / 3600,
+        tod / 60 % 60,
+        tod % 60,
+        ms % 1000
+    )
+}
+
+static NEXT_REQUEST: AtomicU64 = AtomicU64::new(1);
+
+/// A new id for the `req` field of a request span.
+pub fn next_request_id() -> u64 {
+    NEXT_REQUEST.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Whether request spans would be logged; callers may skip extra work
+/// otherwise.
+pub fn spans_enabled() -> bool {
+    tracing::span_enabled!(target: SPAN_TARGET, tracing::Level::TRACE)
+}
+
+/// `--log-level`, `--log-format`.
+#[derive(Args, Debug, Clone, Default)]
+pub struct LogArgs {
+    /// Log filter, e.g. `debug` or `info,kvz::span=trace` [default: $KVZ_LOG or info]
+    #[arg(long, value_name = "FILTER", global = true)]
+    pub log_level: Option<String>,
+    /// Log format [default: $KVZ_LOG_FORMAT or text]
+    #[arg(long, value_enum, global = true)]
+    pub log_format: Option<LogFormat>,
+}
+
+impl LogArgs {
+    /// Install the subscriber for the process.
+    pub fn init(&self) -> Result<()> {
+        let spec = match &self.log_level {
+            Some(s) => s.clone(),
+            None => std::env::var(LOG_ENV).unwrap_or_default(),
+        };
+        let filter = env_filter(&spec).map_err(|e| anyhow!("--log-level/{LOG_ENV}: {e}"))?;
+        let format = match self.log_format {
+            Some(f) => f,
+            None => match std::env::var(LOG_FORMAT_ENV) {
+                Ok(v) if !v.is_empty() => LogFormat::from_str(&v, true)
+                    .map_err(|_| anyhow!("{LOG_FORMAT_ENV}: expected text or json, got {v:?}"))?,
+                _ => LogFormat::Text,
+            },
+        };
+        let fmt = tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_writer(std::io::stderr)
+            .with_span_events(FmtSpan::CLOSE);
+        let installed = match format {
+            LogFormat::Text => fmt.with_ansi(std::io::stderr().is_terminal()).try_init(),
+            LogFormat::Json => fmt.json().flatten_event(true).try_init(),
+        };
+        installed.map_err(|e| anyhow!("logger already initialised: {e}"))
+    }
+}
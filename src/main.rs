@@ -1,8 +1,29 @@
 use anyhow::{anyhow, Context, Result};
-use clap::{Parser, Subcommand};
-use std::collections::HashMap;
-use std::io::{Read, Write};
+use base64::prelude::*;
+use clap::{ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use kvz::acl::AclArgs;
+use kvz::auth::AuthArgs;
+use kvz::config::{ConfigArgs, Configure, Dump, Overlay};
+use kvz::curve::{secret_path, Cert, CurveClientArgs, CurveServerArgs};
+use kvz::hotkeys::HotKeysArgs;
+use kvz::limits::Limits;
+use kvz::listen::{parse_binds, Listeners};
+use kvz::logging::{utc_time, LogArgs};
+use kvz::metrics::{watch_connections, MetricsArgs, INFO_LISTS};
+use kvz::monitor::{MonitorArgs, EVENT_FRAMES};
+use kvz::protocol::{parse_err, read_u64, KvzError};
+use kvz::quota::QuotaArgs;
+use kvz::server::{recv_request, Server};
+use kvz::slowlog::{SlowLogArgs, ENTRY_FRAMES};
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
 use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::info;
+
+/// Chunk size for streaming `put`/`get` (1 MiB)
+const DEFAULT_CHUNK_SIZE: usize = 1 << 20;
 
 /// Simple ZeroMQ-backed K/V store: in-memory, multi-client, binary-friendly.
 #[derive(Parser, Debug)]
@@ -11,51 +32,130 @@ use std::path::PathBuf;
 struct Cli {
     #[command(subcommand)]
     cmd: Cmd,
+    #[command(flatten)]
+    log: LogArgs,
 }
 
 #[derive(Subcommand, Debug)]
 enum Cmd {
     /// Run the server and bind a REP socket
-    Server {
-        /// Bind endpoint, e.g. tcp://*:5555
-        #[arg(long, default_value = "tcp://*:5555")]
-        bind: String,
+    Server(ServerArgs),
+
+    /// Generate a CURVE keypair (ZeroMQ certificate files, Z85 keys)
+    Keygen {
+        /// Write the public certificate here and the secret one to <OUT>_secret
+        /// (if omitted, print both keys to stdout)
+        #[arg(long)]
+        out: Option<PathBuf>,
     },
 
     /// Send a PUT request
     Put {
-        /// Connect endpoint, e.g. tcp://localhost:5555
-        #[arg(long, default_value = "tcp://localhost:5555")]
-        connect: String,
-        /// Key (UTF-8)
-        #[arg(long)]
-        key: String,
+        #[command(flatten)]
+        conn: ConnectArgs,
+        #[command(flatten)]
+        key: KeyArg,
         /// Timestamp as u64 (e.g. milliseconds since Unix epoch)
         #[arg(long)]
         ts: u64,
         /// Read data from a file (if omitted, reads from stdin)
         #[arg(long)]
         file: Option<PathBuf>,
+        /// Values of at least this many bytes are streamed in chunks of this size
+        #[arg(long, default_value_t = DEFAULT_CHUNK_SIZE)]
+        chunk_size: usize,
     },
 
     /// Send a GET request
     Get {
-        /// Connect endpoint, e.g. tcp://localhost:5555
-        #[arg(long, default_value = "tcp://localhost:5555")]
-        connect: String,
-        /// Key (UTF-8)
-        #[arg(long)]
-        key: String,
+        #[command(flatten)]
+        conn: ConnectArgs,
+        #[command(flatten)]
+        key: KeyArg,
         /// Write data to a file (if omitted, writes to stdout)
         #[arg(long)]
         out: Option<PathBuf>,
+        /// Download in chunks of this many bytes
+        #[arg(long, default_value_t = DEFAULT_CHUNK_SIZE)]
+        chunk_size: usize,
+        /// Fetch only bytes starting at this offset (uses GETRANGE)
+        #[arg(long)]
+        offset: Option<u64>,
+        /// Fetch at most this many bytes (uses GETRANGE; default: to the end)
+        #[arg(long)]
+        length: Option<u64>,
+    },
+
+    /// Query the server's request size limits
+    Limits {
+        #[command(flatten)]
+        conn: ConnectArgs,
+    },
+
+    /// Show server status: uptime, keys, memory, shard balance, ops, clients
+    Info {
+        #[command(flatten)]
+        conn: ConnectArgs,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = InfoFormat::Table)]
+        format: InfoFormat,
+    },
+
+    /// Show the server's slowest recent requests, newest first
+    Slowlog {
+        #[command(flatten)]
+        conn: ConnectArgs,
+        /// Show at most this many entries
+        #[arg(long)]
+        count: Option<u64>,
+        /// Clear the slow log instead of showing it
+        #[arg(long)]
+        reset: bool,
+    },
+
+    /// Show the most read and written keys and the largest values (approximate)
+    Hotkeys {
+        #[command(flatten)]
+        conn: ConnectArgs,
+        /// Which list to show (default: all three)
+        #[arg(long, value_enum)]
+        kind: Option<HotKeysKind>,
+        /// Show at most this many keys per list
+        #[arg(long, default_value_t = 10)]
+        count: usize,
+    },
+
+    /// Print requests streamed by a server started with --monitor-bind
+    Monitor {
+        /// Monitor endpoint of the server
+        #[arg(long, default_value = "tcp://localhost:5556")]
+        connect: String,
+        #[command(flatten)]
+        curve: CurveClientArgs,
+        /// Only show these commands (repeatable)
+        #[arg(long = "command", value_name = "CMD")]
+        commands: Vec<String>,
+        /// Exit after this many requests
+        #[arg(long)]
+        count: Option<u64>,
+    },
+
+    /// Manage namespaces: list, create, drop, flush, stats
+    Ns {
+        action: NsAction,
+        /// Namespace name (all actions except list)
+        name: Option<String>,
+        /// Byte quota for `create` (0 = none)
+        #[arg(long, default_value_t = 0)]
+        quota: u64,
+        #[command(flatten)]
+        conn: ConnectArgs,
     },
 
     /// Quick concurrency demo: spawn N clients doing mixed PUT/GET
     Demo {
-        /// Connect endpoint
-        #[arg(long, default_value = "tcp://localhost:5555")]
-        connect: String,
+        #[command(flatten)]
+        conn: ConnectArgs,
         /// Number of client threads
         #[arg(long, default_value_t = 8)]
         clients: usize,
@@ -65,177 +165,694 @@ enum Cmd {
     },
 }
 
-#[derive(Clone)]
-struct Value {
-    ts: u64,
-    data: Vec<u8>,
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum InfoFormat {
+    Table,
+    Json,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum HotKeysKind {
+    Reads,
+    Writes,
+    Largest,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum NsAction {
+    List,
+    Create,
+    Drop,
+    Flush,
+    Stats,
+}
+
+/// Options of `kvz server`.
+#[derive(Args, Debug)]
+struct ServerArgs {
+    /// Bind endpoint, ENDPOINT[,curve=FILE|none][,allowlist=FILE|none] (repeatable)
+    #[arg(long, value_name = "ENDPOINT", default_value = "tcp://*:5555")]
+    bind: Vec<String>,
+    #[command(flatten)]
+    limits: Limits,
+    #[command(flatten)]
+    curve: CurveServerArgs,
+    #[command(flatten)]
+    auth: AuthArgs,
+    #[command(flatten)]
+    acl: AclArgs,
+    #[command(flatten)]
+    quota: QuotaArgs,
+    #[command(flatten)]
+    metrics: MetricsArgs,
+    #[command(flatten)]
+    slowlog: SlowLogArgs,
+    #[command(flatten)]
+    monitor: MonitorArgs,
+    #[command(flatten)]
+    hotkeys: HotKeysArgs,
+    #[command(flatten)]
+    config: ConfigArgs,
+}
+
+impl Configure for ServerArgs {
+    fn configure(&mut self, cfg: &mut Overlay) -> Result<()> {
+        cfg.set("bind", "bind", &mut self.bind)?;
+        cfg.ignore("workers");
+        cfg.ignore("shards");
+        self.limits.configure(cfg)?;
+        self.curve.configure(cfg)?;
+        self.auth.configure(cfg)?;
+        self.acl.configure(cfg)?;
+        self.quota.configure(cfg)?;
+        self.metrics.configure(cfg)?;
+        self.slowlog.configure(cfg)?;
+        self.monitor.configure(cfg)?;
+        self.hotkeys.configure(cfg)
+    }
+
+    fn dump(&self, out: &mut Dump) {
+        out.put("bind", &self.bind);
+        self.limits.dump(out);
+        self.curve.dump(out);
+        self.auth.dump(out);
+        self.acl.dump(out);
+        self.quota.dump(out);
+        self.metrics.dump(out);
+        self.slowlog.dump(out);
+        self.monitor.dump(out);
+        self.hotkeys.dump(out);
+    }
+
+    fn validate(&self) -> Result<()> {
+        parse_binds(&self.bind)?;
+        self.limits.validate()?;
+        self.quota.validate()?;
+        self.monitor.validate()
+    }
+}
+
+/// Where and how a client connects.
+#[derive(Args, Debug, Clone)]
+struct ConnectArgs {
+    /// Connect endpoint, e.g. tcp://localhost:5555
+    #[arg(long, default_value = "tcp://localhost:5555")]
+    connect: String,
+    /// Namespace to use instead of `default`
+    #[arg(long, short = 'n')]
+    namespace: Option<String>,
+    #[command(flatten)]
+    curve: CurveClientArgs,
+}
+
+impl ConnectArgs {
+    /// REQ socket connected to the server, with CURVE if requested.
+    fn connect(&self, ctx: &zmq::Context) -> Result<Client> {
+        let sock = ctx.socket(zmq::REQ)?;
+        self.curve.apply(&sock)?;
+        sock.connect(&self.connect)
+            .with_context(|| format!("connect {}", self.connect))?;
+        Ok(Client { sock, namespace: self.namespace.clone() })
+    }
+}
+
+/// A connected client; requests go to its namespace, if one was given.
+struct Client {
+    sock: zmq::Socket,
+    namespace: Option<String>,
+}
+
+/// Key given as UTF-8 text, hex or base64; exactly one is required.
+#[derive(Args, Debug)]
+#[group(required = true, multiple = false)]
+struct KeyArg {
+    /// Key (UTF-8)
+    #[arg(long)]
+    key: Option<String>,
+    /// Key as hex-encoded bytes, e.g. 00ff10
+    #[arg(long)]
+    key_hex: Option<String>,
+    /// Key as base64-encoded bytes (standard alphabet)
+    #[arg(long)]
+    key_base64: Option<String>,
+}
+
+impl KeyArg {
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        if let Some(k) = &self.key {
+            Ok(k.as_bytes().to_vec())
+        } else if let Some(h) = &self.key_hex {
+            hex::decode(h).context("--key-hex")
+        } else if let Some(b) = &self.key_base64 {
+            BASE64_STANDARD.decode(b).context("--key-base64")
+        } else {
+            Err(anyhow!("one of --key, --key-hex or --key-base64 is required"))
+        }
+    }
 }
 
 fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let matches: ArgMatches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    cli.log.init()?;
     match cli.cmd {
-        Cmd::Server { bind } => run_server(&bind),
-        Cmd::Put { connect, key, ts, file } => client_put(&connect, &key, ts, file),
-        Cmd::Get { connect, key, out } => client_get(&connect, &key, out),
-        Cmd::Demo { connect, clients, iters } => demo(&connect, clients, iters),
+        Cmd::Server(args) => run_server(args, matches.subcommand_matches("server").expect("server subcommand")),
+        Cmd::Keygen { out } => keygen(out),
+        Cmd::Put { conn, key, ts, file, chunk_size } => {
+            client_put(&conn, &key.to_bytes()?, ts, file, chunk_size.max(1))
+        }
+        Cmd::Get { conn, key, out, chunk_size, offset: None, length: None } => {
+            client_get(&conn, &key.to_bytes()?, out, chunk_size.max(1))
+        }
+        Cmd::Get { conn, key, out, chunk_size, offset, length } => {
+            let offset = offset.unwrap_or(0);
+            client_get_range(&conn, &key.to_bytes()?, offset, length, out, chunk_size.max(1))
+        }
+        Cmd::Limits { conn } => client_limits(&conn),
+        Cmd::Info { conn, format } => client_info(&conn, format),
+        Cmd::Slowlog { conn, count, reset } => client_slowlog(&conn, count, reset),
+        Cmd::Hotkeys { conn, kind, count } => client_hotkeys(&conn, kind, count),
+        Cmd::Monitor { connect, curve, commands, count } => monitor(&connect, &curve, &commands, count),
+        Cmd::Ns { action, name, quota, conn } => client_ns(&conn, action, name, quota),
+        Cmd::Demo { conn, clients, iters } => demo(&conn, clients, iters),
     }
 }
 
-fn run_server(bind: &str) -> Result<()> {
+fn run_server(mut args: ServerArgs, matches: &ArgMatches) -> Result<()> {
+    if !args.config.clone().resolve(matches, &mut args)? {
+        return Ok(());
+    }
+    // In-memory store (single-threaded server, so one shard is enough)
+    let mut server = Server::new(1, args.limits);
+    server.acl = args.acl.load()?;
+    server.rate_limit = args.quota.rate_limiter();
+    server.prefix_quotas = args.quota.prefix_quotas()?;
+    server.namespaces.max = args.quota.max_namespaces;
+    server.slowlog = args.slowlog.slowlog();
+    server.hotkeys = args.hotkeys.hotkeys();
+
     let ctx = zmq::Context::new();
-    let socket = ctx.socket(zmq::REP)?;
-    socket.bind(bind).with_context(|| format!("bind {}", bind))?;
+    let mut socket = ctx.socket(zmq::REP)?;
+    args.limits.apply(&socket)?;
+    let listeners = Listeners::new(&ctx, &args.bind, args.monitor.monitor_bind.as_deref(), &args.curve, &args.auth)?;
+    let monitor = args.monitor.start(&ctx, &listeners)?;
+    let monitor_ep = monitor.as_ref().map(|(_, b)| b.endpoint.clone());
+    server.monitor = monitor.map(|(m, _)| m);
+    let server = Arc::new(server);
+    watch_connections(&ctx, &socket, Arc::clone(&server))?;
+    info!("kvz server started");
+    for b in listeners.bind(&socket)? {
+        b.log();
+    }
+    if let Some(addr) = args.metrics.start(Arc::clone(&server))? {
+        info!(url = %format_args!("http://{addr}/metrics"), "metrics listening");
+    }
+    if let Some(ep) = monitor_ep {
+        info!(endpoint = ep.as_str(), "monitor stream listening");
+    }
 
-    // In-memory store
-    let mut store: HashMap<String, Value> = HashMap::new();
-    eprintln!("kvz server listening on {bind}");
+    server.serve(&mut socket, 0, recv_request)
+}
 
-    loop {
-        let msg = socket.recv_multipart(0)?;
-        if msg.is_empty() {
-            send_err(&socket, "empty message")?;
-            continue;
+impl Client {
+    /// Send a request and split the reply into status and payload frames.
+    /// `ERR` replies become a [`kvz::protocol::KvzError`].
+    fn call(&self, op: &str, frames: &[&[u8]]) -> Result<(String, Vec<Vec<u8>>)> {
+        if let Some(ns) = &self.namespace {
+            self.sock.send_multipart([b"NS".as_slice(), ns.as_bytes()], zmq::SNDMORE)?;
         }
-        let cmd = std::str::from_utf8(&msg[0]).unwrap_or("");
-        match cmd {
-            "PUT" => {
-                // Expect 4 frames: "PUT", key, ts(8), data
-                if msg.len() != 4 {
-                    send_err(&socket, "PUT expects 4 frames")?;
-                    continue;
-                }
-                let key = String::from_utf8(msg[1].clone())
-                    .map_err(|_| anyhow!("key not utf-8"))?;
-                if msg[2].len() != 8 {
-                    send_err(&socket, "timestamp must be 8 bytes (u64 BE)")?;
-                    continue;
-                }
-                let mut tsb = [0u8; 8];
-                tsb.copy_from_slice(&msg[2]);
-                let ts = u64::from_be_bytes(tsb);
-                let data = msg[3].clone();
-
-                match store.get(&key) {
-                    Some(v) if ts < v.ts => {
-                        socket.send_multipart(&[b"STALE".as_slice()], 0)?;
-                    }
-                    _ => {
-                        store.insert(key, Value { ts, data });
-                        socket.send_multipart(&[b"OK".as_slice()], 0)?;
-                    }
-                }
-            }
-            "GET" => {
-                // Expect 2 frames: "GET", key
-                if msg.len() != 2 {
-                    send_err(&socket, "GET expects 2 frames")?;
-                    continue;
-                }
-                let key = String::from_utf8(msg[1].clone())
-                    .map_err(|_| anyhow!("key not utf-8"));
-                if key.is_err() {
-                    send_err(&socket, "key not utf-8")?;
-                    continue;
-                }
-                let key = key.unwrap();
-                if let Some(v) = store.get(&key) {
-                    let tsb = v.ts.to_be_bytes();
-                    socket.send_multipart(&[b"OK".as_slice(), &tsb, &v.data], 0)?;
-                } else {
-                    socket.send_multipart(&[b"MISS".as_slice()], 0)?;
-                }
-            }
-            _ => {
-                send_err(&socket, "unknown command")?;
-            }
+        self.sock.send_multipart(frames, 0)?;
+        let mut rep = self.sock.recv_multipart(0)?;
+        if rep.is_empty() {
+            return Err(anyhow!("empty reply"));
+        }
+        let status = String::from_utf8_lossy(&rep[0]).into_owned();
+        if status == "ERR" {
+            return Err(anyhow::Error::new(parse_err(&rep)).context(format!("{op} ERR")));
         }
+        rep.remove(0);
+        Ok((status, rep))
     }
 }
 
-fn send_err(sock: &zmq::Socket, msg: &str) -> Result<()> {
-    sock.send_multipart(&[b"ERR".as_slice(), msg.as_bytes()], 0)?;
-    Ok(())
+/// Read up to `n` bytes, short only at EOF.
+fn read_chunk(r: &mut impl Read, n: usize) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(n);
+    r.take(n as u64).read_to_end(&mut buf)?;
+    Ok(buf)
 }
 
-fn client_put(connect: &str, key: &str, ts: u64, file: Option<PathBuf>) -> Result<()> {
+fn client_put(conn: &ConnectArgs, key: &[u8], ts: u64, file: Option<PathBuf>, chunk_size: usize) -> Result<()> {
     let ctx = zmq::Context::new();
-    let sock = ctx.socket(zmq::REQ)?;
-    sock.connect(connect).with_context(|| format!("connect {}", connect))?;
+    let client = conn.connect(&ctx)?;
 
-    let data = match file {
-        Some(p) => std::fs::read(p)?,
-        None => {
-            let mut buf = Vec::new();
-            std::io::stdin().read_to_end(&mut buf)?;
-            buf
-        }
+    let mut input: Box<dyn Read> = match file {
+        Some(p) => Box::new(File::open(&p).with_context(|| format!("open {}", p.display()))?),
+        None => Box::new(std::io::stdin().lock()),
     };
 
-    let tsb = ts.to_be_bytes().to_vec();
-    sock.send_multipart(&[b"PUT".as_slice(), key.as_bytes(), &tsb, &data], 0)?;
-    let rep = sock.recv_multipart(0)?;
-    match rep.first().map(|b| std::str::from_utf8(b).unwrap_or("")) {
-        Some("OK") => {
-            eprintln!("PUT OK ({} bytes)", data.len());
+    // Small values go out as a single PUT; anything larger is streamed.
+    let tsb = ts.to_be_bytes();
+    let first = read_chunk(&mut input, chunk_size)?;
+    let (status, total) = if first.len() < chunk_size {
+        let (status, _) = client.call("PUT", &[b"PUT", key, &tsb, &first])?;
+        (status, first.len())
+    } else {
+        let (_, rep) = client.call("PUTBEGIN", &[b"PUTBEGIN", key, &tsb])?;
+        let sid = rep.first().and_then(|f| read_u64(f)).ok_or_else(|| anyhow!("malformed PUTBEGIN reply"))?;
+        let sidb = sid.to_be_bytes();
+        let mut total = 0;
+        let send_chunks = || -> Result<()> {
+            let mut chunk = first;
+            while !chunk.is_empty() {
+                client.call("PUTCHUNK", &[b"PUTCHUNK", &sidb, &chunk])?;
+                total += chunk.len();
+                chunk = read_chunk(&mut input, chunk_size)?;
+            }
             Ok(())
+        };
+        if let Err(e) = send_chunks() {
+            // Best effort: free the server-side buffer
+            let _ = client.call("PUTABORT", &[b"PUTABORT", &sidb]);
+            return Err(e);
         }
-        Some("STALE") => {
-            eprintln!("PUT STALE (newer value already present)");
+        let (status, _) = client.call("PUTCOMMIT", &[b"PUTCOMMIT", &sidb])?;
+        (status, total)
+    };
+    match status.as_str() {
+        "OK" => {
+            eprintln!("PUT OK ({} bytes)", total);
             Ok(())
         }
-        Some("ERR") => {
-            let msg = rep.get(1).and_then(|b| std::str::from_utf8(b).ok()).unwrap_or("");
-            Err(anyhow!("PUT ERR: {msg}"))
+        "STALE" => {
+            eprintln!("PUT STALE (newer value already present)");
+            Ok(())
         }
         other => Err(anyhow!("unexpected reply: {:?}", other)),
     }
 }
 
-fn client_get(connect: &str, key: &str, out: Option<PathBuf>) -> Result<()> {
+fn client_get(conn: &ConnectArgs, key: &[u8], out: Option<PathBuf>, chunk_size: usize) -> Result<()> {
     let ctx = zmq::Context::new();
-    let sock = ctx.socket(zmq::REQ)?;
-    sock.connect(connect).with_context(|| format!("connect {}", connect))?;
+    let client = conn.connect(&ctx)?;
 
-    sock.send_multipart(&[b"GET".as_slice(), key.as_bytes()], 0)?;
-    let rep = sock.recv_multipart(0)?;
-    if rep.is_empty() {
-        return Err(anyhow!("empty reply"));
-    }
-    match std::str::from_utf8(&rep[0]).unwrap_or("") {
+    let lenb = (chunk_size as u64).to_be_bytes();
+    let (status, rep) = client.call("GETBEGIN", &[b"GETBEGIN", key, &lenb])?;
+    match status.as_str() {
         "OK" => {
-            if rep.len() != 3 || rep[1].len() != 8 {
-                return Err(anyhow!("malformed OK reply"));
-            }
-            let mut tsb = [0u8; 8];
-            tsb.copy_from_slice(&rep[1]);
-            let ts = u64::from_be_bytes(tsb);
-            let data = &rep[2];
-
-            eprintln!("GET OK: ts={ts} size={} bytes", data.len());
-            match out {
-                Some(p) => std::fs::write(p, data)?,
-                None => {
-                    // Write binary to stdout
-                    let mut stdout = std::io::stdout().lock();
-                    stdout.write_all(data)?;
-                    stdout.flush()?;
+            let (Some(sid), Some(ts), Some(total), Some(first)) = (
+                rep.first().and_then(|f| read_u64(f)),
+                rep.get(1).and_then(|f| read_u64(f)),
+                rep.get(2).and_then(|f| read_u64(f)),
+                rep.get(3),
+            ) else {
+                return Err(anyhow!("malformed GETBEGIN reply"));
+            };
+            eprintln!("GET OK: ts={ts} size={total} bytes");
+
+            let mut w = open_out(out)?;
+            w.write_all(first)?;
+            let mut written = first.len() as u64;
+            let sidb = sid.to_be_bytes();
+            while written < total {
+                let (_, rep) = client.call("GETCHUNK", &[b"GETCHUNK", &sidb, &lenb])?;
+                let chunk = rep.first().ok_or_else(|| anyhow!("malformed GETCHUNK reply"))?;
+                if chunk.is_empty() {
+                    return Err(anyhow!("GETCHUNK returned no data at offset {written}"));
                 }
+                w.write_all(chunk)?;
+                written += chunk.len() as u64;
             }
+            w.flush()?;
             Ok(())
         }
         "MISS" => {
             eprintln!("GET MISS");
             Ok(())
         }
-        "ERR" => {
-            let msg = rep.get(1).and_then(|b| std::str::from_utf8(b).ok()).unwrap_or("");
-            Err(anyhow!("GET ERR: {msg}"))
-        }
         other => Err(anyhow!("unexpected reply: {other}")),
     }
 }
 
-fn demo(connect: &str, clients: usize, iters: usize) -> Result<()> {
+fn client_get_range(
+    conn: &ConnectArgs,
+    key: &[u8],
+    offset: u64,
+    length: Option<u64>,
+    out: Option<PathBuf>,
+    chunk_size: usize,
+) -> Result<()> {
+    let ctx = zmq::Context::new();
+    let client = conn.connect(&ctx)?;
+
+    // Fetch the range piecewise; every piece must come from the same version.
+    let mut w: Option<Box<dyn Write>> = None;
+    let mut out = out;
+    let mut pos = offset;
+    let mut remaining = length.unwrap_or(u64::MAX);
+    let mut first_ts = None;
+    loop {
+        let want = remaining.min(chunk_size as u64);
+        let (status, rep) = client.call(
+            "GETRANGE",
+            &[b"GETRANGE", key, &pos.to_be_bytes(), &want.to_be_bytes()],
+        )?;
+        match status.as_str() {
+            "OK" => {}
+            "MISS" => {
+                eprintln!("GET MISS");
+                return Ok(());
+            }
+            other => return Err(anyhow!("unexpected reply: {other}")),
+        }
+        let (Some(ts), Some(total), Some(data)) = (
+            rep.first().and_then(|f| read_u64(f)),
+            rep.get(1).and_then(|f| read_u64(f)),
+            rep.get(2),
+        ) else {
+            return Err(anyhow!("malformed GETRANGE reply"));
+        };
+        match first_ts {
+            None => {
+                let start = offset.min(total);
+                let end = offset.saturating_add(length.unwrap_or(u64::MAX)).min(total);
+                eprintln!("GET OK: ts={ts} size={total} bytes, range {start}..{end} ({} bytes)", end - start);
+                first_ts = Some(ts);
+                w = Some(open_out(out.take())?);
+            }
+            Some(t) if t != ts => {
+                return Err(anyhow!("value changed during ranged read (ts {t} -> {ts})"));
+            }
+            Some(_) => {}
+        }
+        let w = w.as_mut().expect("writer opened on first reply");
+        w.write_all(data)?;
+        pos += data.len() as u64;
+        remaining -= data.len() as u64;
+        if data.is_empty() || remaining == 0 || pos >= total {
+            w.flush()?;
+            return Ok(());
+        }
+    }
+}
+
+/// Output file, or stdout if none given.
+fn open_out(out: Option<PathBuf>) -> Result<Box<dyn Write>> {
+    Ok(match out {
+        Some(p) => Box::new(BufWriter::new(
+            File::create(&p).with_context(|| format!("create {}", p.display()))?,
+        )),
+        // Write binary to stdout
+        None => Box::new(std::io::stdout().lock()),
+    })
+}
+
+fn client_limits(conn: &ConnectArgs) -> Result<()> {
+    let ctx = zmq::Context::new();
+    let client = conn.connect(&ctx)?;
+
+    let (_, rep) = client.call("LIMITS", &[b"LIMITS"])?;
+    let vals: Option<Vec<u64>> = rep.iter().map(|f| read_u64(f)).collect();
+    let Some([key, value, msg]) = vals.as_deref() else {
+        return Err(anyhow!("malformed LIMITS reply"));
+    };
+    println!("max_key_bytes   : {key}");
+    println!("max_value_bytes : {value}");
+    println!("max_msg_bytes   : {msg}");
+    Ok(())
+}
+
+/// An INFO value as JSON: numbers stay numbers (integers exactly), the
+/// fields in [`INFO_LISTS`] become arrays, anything else is a string.
+fn info_json(name: &str, value: &str) -> Value {
+    let scalar = |v: &str| match (v.parse::<u64>(), v.parse::<f64>()) {
+        (Ok(n), _) => Value::from(n),
+        (_, Ok(n)) if n.is_finite() => Value::from(n),
+        _ => Value::from(v),
+    };
+    if INFO_LISTS.contains(&name) {
+        return Value::Array(value.split_whitespace().map(scalar).collect());
+    }
+    scalar(value)
+}
+
+fn client_info(conn: &ConnectArgs, format: InfoFormat) -> Result<()> {
+    let ctx = zmq::Context::new();
+    let client = conn.connect(&ctx)?;
+
+    let (_, rep) = client.call("INFO", &[b"INFO"])?;
+    if rep.len() % 2 != 0 {
+        return Err(anyhow!("malformed INFO reply"));
+    }
+    let fields: Vec<(String, String)> = rep
+        .chunks(2)
+        .map(|kv| (String::from_utf8_lossy(&kv[0]).into_owned(), String::from_utf8_lossy(&kv[1]).into_owned()))
+        .collect();
+    match format {
+        InfoFormat::Json => {
+            let json = Value::Object(fields.iter().map(|(k, v)| (k.clone(), info_json(k, v))).collect());
+            println!("{}", serde_json::to_string_pretty(&json)?);
+        }
+        InfoFormat::Table => {
+            let width = fields.iter().map(|(k, _)| k.len()).max().unwrap_or(0);
+            for (k, v) in &fields {
+                if INFO_LISTS.contains(&k.as_str()) {
+                    // One row per shard reads better than a long list.
+                    println!("{k:<width$} :");
+                    for (i, n) in v.split(' ').enumerate() {
+                        println!("{:<width$}   [{i:>3}] {n}", "");
+                    }
+                } else {
+                    println!("{k:<width$} : {v}");
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn client_slowlog(conn: &ConnectArgs, count: Option<u64>, reset: bool) -> Result<()> {
+    let ctx = zmq::Context::new();
+    let client = conn.connect(&ctx)?;
+
+    if reset {
+        let (_, rep) = client.call("SLOWLOGRESET", &[b"SLOWLOGRESET"])?;
+        let removed =
+            rep.first().and_then(|f| read_u64(f)).ok_or_else(|| anyhow!("malformed SLOWLOGRESET reply"))?;
+        eprintln!("slow log cleared ({removed} entries removed)");
+        return Ok(());
+    }
+    let count = count.map(u64::to_be_bytes);
+    let mut req: Vec<&[u8]> = vec![b"SLOWLOG"];
+    if let Some(c) = &count {
+        req.push(c);
+    }
+    let (_, rep) = client.call("SLOWLOG", &req)?;
+    if rep.len() % ENTRY_FRAMES != 0 {
+        return Err(anyhow!("malformed SLOWLOG reply"));
+    }
+    println!(
+        "{:>6} {:<23} {:>10} {:>10} {:>6} {:<12} {:>10}  key",
+        "id", "time (UTC)", "dur_us", "lock_us", "worker", "command", "bytes"
+    );
+    for e in rep.chunks(ENTRY_FRAMES) {
+        let nums: Option<Vec<u64>> = e[..6].iter().map(|f| read_u64(f)).collect();
+        let Some(&[id, time_ms, dur_us, lock_us, worker, bytes]) = nums.as_deref() else {
+            return Err(anyhow!("malformed SLOWLOG entry"));
+        };
+        let text = |f: &[u8]| String::from_utf8_lossy(f).into_owned();
+        let (command, ns, client) = (text(&e[6]), text(&e[7]), text(&e[9]));
+        let mut key = display_key(&e[8]);
+        if !ns.is_empty() {
+            key = format!("{ns}/{key}");
+        }
+        println!(
+            "{id:>6} {:<23} {dur_us:>10} {lock_us:>10} {worker:>6} {command:<12} {bytes:>10}  {key}  ({client})",
+            utc_time(time_ms)
+        );
+    }
+    Ok(())
+}
+
+fn client_hotkeys(conn: &ConnectArgs, kind: Option<HotKeysKind>, count: usize) -> Result<()> {
+    let ctx = zmq::Context::new();
+    let client = conn.connect(&ctx)?;
+
+    let kinds = match kind {
+        Some(k) => vec![k],
+        None => vec![HotKeysKind::Reads, HotKeysKind::Writes, HotKeysKind::Largest],
+    };
+    for (i, kind) in kinds.into_iter().enumerate() {
+        let (name, column) = match kind {
+            HotKeysKind::Reads => ("reads", "reads (est.)"),
+            HotKeysKind::Writes => ("writes", "writes (est.)"),
+            HotKeysKind::Largest => ("largest", "bytes"),
+        };
+        let (_, rep) = client.call("HOTKEYS", &[b"HOTKEYS", name.as_bytes()])?;
+        if rep.len() % 3 != 0 {
+            return Err(anyhow!("malformed HOTKEYS reply"));
+        }
+        if i > 0 {
+            println!();
+        }
+        println!("{column:>14}  key");
+        for e in rep.chunks(3).take(count) {
+            let n = read_u64(&e[2]).ok_or_else(|| anyhow!("malformed HOTKEYS entry"))?;
+            let ns = String::from_utf8_lossy(&e[0]);
+            println!("{n:>14}  {ns}/{}", display_key(&e[1]));
+        }
+    }
+    Ok(())
+}
+
+fn monitor(connect: &str, curve: &CurveClientArgs, commands: &[String], count: Option<u64>) -> Result<()> {
+    let ctx = zmq::Context::new();
+    let sock = ctx.socket(zmq::SUB)?;
+    curve.apply(&sock)?;
+    let commands: Vec<String> = commands.iter().map(|c| c.to_ascii_uppercase()).collect();
+    if commands.is_empty() {
+        sock.set_subscribe(b"")?;
+    }
+    // Subscriptions match prefixes (GET also matches GETBEGIN), so the
+    // exact command is checked again below.
+    for c in &commands {
+        sock.set_subscribe(c.as_bytes())?;
+    }
+    sock.connect(connect).with_context(|| format!("connect {connect}"))?;
+    eprintln!("monitoring {connect} (Ctrl-C to stop)");
+
+    let mut shown = 0;
+    while count.is_none_or(|n| shown < n) {
+        let ev = sock.recv_multipart(0)?;
+        if ev.len() != EVENT_FRAMES {
+            continue;
+        }
+        let text = |f: &[u8]| String::from_utf8_lossy(f).into_owned();
+        let command = text(&ev[0]);
+        if !commands.is_empty() && !commands.contains(&command) {
+            continue;
+        }
+        let num = |i: usize| read_u64(&ev[i]).unwrap_or(0);
+        let (time_us, worker, dur_us, req_bytes, reply_bytes) = (num(1), num(2), num(3), num(7), num(9));
+        let (client, ns, kind) = (text(&ev[4]), text(&ev[5]), text(&ev[8]));
+        let mut key = if ev[6].is_empty() { String::new() } else { display_key(&ev[6]) };
+        if !ns.is_empty() {
+            key = format!("{ns}/{key}");
+        }
+        let mut line = format!(
+            "{} w{worker:<2} {client:<22} {command:<10} {key:<32} {req_bytes:>8}B -> {kind:<5} {reply_bytes:>8}B {dur_us:>7}us",
+            &utc_time(time_us / 1000)[11..],
+        );
+        if !ev[10].is_empty() {
+            line.push_str(&format!("  {:?}", String::from_utf8_lossy(&ev[10])));
+        }
+        println!("{line}");
+        shown += 1;
+    }
+    Ok(())
+}
+
+/// A key as text if it is printable UTF-8, otherwise as `hex:...`.
+fn display_key(key: &[u8]) -> String {
+    match std::str::from_utf8(key) {
+        Ok(s) if !s.chars().any(char::is_control) => s.to_string(),
+        _ => format!("hex:{}", hex::encode(key)),
+    }
+}
+
+/// Counters from an NSSTATS reply.
+struct NsStats {
+    keys: u64,
+    bytes: u64,
+    quota: u64,
+    gets: u64,
+    hits: u64,
+    puts: u64,
+    stale: u64,
+}
+
+fn ns_stats(client: &Client, name: &str) -> Result<NsStats> {
+    let (_, rep) = client.call("NSSTATS", &[b"NSSTATS", name.as_bytes()])?;
+    let vals: Option<Vec<u64>> = rep.iter().map(|f| read_u64(f)).collect();
+    let Some(&[keys, bytes, quota, gets, hits, puts, stale]) = vals.as_deref() else {
+        return Err(anyhow!("malformed NSSTATS reply"));
+    };
+    Ok(NsStats { keys, bytes, quota, gets, hits, puts, stale })
+}
+
+fn client_ns(conn: &ConnectArgs, action: NsAction, name: Option<String>, quota: u64) -> Result<()> {
+    let ctx = zmq::Context::new();
+    let client = conn.connect(&ctx)?;
+
+    let name = match (action, name) {
+        (NsAction::List, _) => {
+            let (_, names) = client.call("NSLIST", &[b"NSLIST"])?;
+            println!("{:<24} {:>12} {:>14} {:>14}", "namespace", "keys", "bytes", "quota");
+            for name in names {
+                let name = String::from_utf8_lossy(&name);
+                let st = ns_stats(&client, &name)?;
+                let quota = if st.quota == 0 { "-".to_string() } else { st.quota.to_string() };
+                println!("{:<24} {:>12} {:>14} {:>14}", name, st.keys, st.bytes, quota);
+            }
+            return Ok(());
+        }
+        (_, Some(name)) => name,
+        (_, None) => return Err(anyhow!("a namespace name is required")),
+    };
+    match action {
+        NsAction::List => unreachable!("handled above"),
+        NsAction::Create => {
+            client.call("NSCREATE", &[b"NSCREATE", name.as_bytes(), &quota.to_be_bytes()])?;
+            eprintln!("namespace {name} ready");
+        }
+        NsAction::Drop => {
+            client.call("NSDROP", &[b"NSDROP", name.as_bytes()])?;
+            eprintln!("namespace {name} dropped");
+        }
+        NsAction::Flush => {
+            let (_, rep) = client.call("NSFLUSH", &[b"NSFLUSH", name.as_bytes()])?;
+            let removed = rep.first().and_then(|f| read_u64(f)).ok_or_else(|| anyhow!("malformed NSFLUSH reply"))?;
+            eprintln!("namespace {name} flushed ({removed} keys removed)");
+        }
+        NsAction::Stats => {
+            let st = ns_stats(&client, &name)?;
+            println!("keys   : {}", st.keys);
+            println!("bytes  : {}", st.bytes);
+            println!("quota  : {}", if st.quota == 0 { "none".to_string() } else { st.quota.to_string() });
+            println!("gets   : {} ({} hits)", st.gets, st.hits);
+            println!("puts   : {} ({} stale)", st.puts, st.stale);
+        }
+    }
+    Ok(())
+}
+
+fn keygen(out: Option<PathBuf>) -> Result<()> {
+    let cert = Cert::generate()?;
+    match out {
+        Some(p) => {
+            cert.save(&p)?;
+            eprintln!("wrote {} and {}", p.display(), secret_path(&p).display());
+            println!("{}", cert.public_z85());
+        }
+        None => {
+            let secret = cert.secret.as_deref().expect("generated certs have a secret key");
+            println!("public-key = \"{}\"", cert.public_z85());
+            println!("secret-key = \"{}\"", zmq::z85_encode(secret)?);
+        }
+    }
+    Ok(())
+}
+
+/// Treat an `ERR` reply as a normal outcome; the demo only keeps load on the
+/// server, so a rejected request must not stop the client. Transport errors still do.
+fn ignore_err<T>(res: Result<T>) -> Result<()> {
+    match res {
+        Err(e) if e.downcast_ref::<KvzError>().is_none() => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn demo(conn: &ConnectArgs, clients: usize, iters: usize) -> Result<()> {
     use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::Arc;
     use std::thread;
@@ -244,12 +861,11 @@ fn demo(connect: &str, clients: usize, iters: usize) -> Result<()> {
     let mut handles = Vec::new();
 
     for id in 0..clients {
-        let connect = connect.to_string();
+        let conn = conn.clone();
         let done = Arc::clone(&done);
         handles.push(thread::spawn(move || -> Result<()> {
             let ctx = zmq::Context::new();
-            let sock = ctx.socket(zmq::REQ)?;
-            sock.connect(&connect)?;
+            let client = conn.connect(&ctx)?;
 
             for i in 0..iters {
                 // alternate PUT/GET
@@ -258,12 +874,10 @@ fn demo(connect: &str, clients: usize, iters: usize) -> Result<()> {
                 let data = format!("hello-from-{}-{}", id, i).into_bytes();
 
                 // PUT
-                sock.send_multipart(&[b"PUT".as_slice(), key.as_bytes(), &ts.to_be_bytes(), &data], 0)?;
-                let _ = sock.recv_multipart(0)?;
+                ignore_err(client.call("PUT", &[b"PUT", key.as_bytes(), &ts.to_be_bytes(), &data]))?;
 
                 // GET
-                sock.send_multipart(&[b"GET".as_slice(), key.as_bytes()], 0)?;
-                let _ = sock.recv_multipart(0)?;
+                ignore_err(client.call("GET", &[b"GET", key.as_bytes()]))?;
             }
             done.fetch_add(1, Ordering::Relaxed);
             Ok(())
@@ -272,7 +886,7 @@ fn demo(connect: &str, clients: usize, iters: usize) -> Result<()> {
 
     for h in handles {
         if let Err(e) = h.join().unwrap_or_else(|_| Err(anyhow!("thread panic"))) {
-            eprintln!("demo client error: {e}");
+            tracing::error!(error = %format!("{e:#}"), "demo client failed");
         }
     }
     eprintln!("demo complete: {} clients x {} iters", clients, iters);
@@ -0,0 +1,413 @@
+//! Request metrics, the `INFO` summary and the optional Prometheus endpoint
+//! (`--metrics-bind`).
+//!
+//! Counters are plain atomics updated by the workers; the HTTP listener
+//! renders them in the Prometheus text format on every scrape. The listener
+//! is deliberately minimal: it answers `GET /metrics`, one thread per
+//! connection up to [`MAX_SCRAPERS`], and gives each client a few KiB and a
+//! couple of seconds to send its request. Connected clients are counted from
+//! the client-facing socket's monitor events (see [`watch_connections`]).
+
+use crate::server::{Reply, Server};
+use anyhow::{Context, Result};
+use clap::Args;
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Commands with their own metric labels; anything else is counted as `OTHER`.
+pub const COMMANDS: &[&str] = &[
+    "PUT", "GET", "GETRANGE", "LIMITS", "PUTBEGIN", "PUTCHUNK", "PUTCOMMIT", "PUTABORT", "GETBEGIN",
+    "GETCHUNK", "GETEND", "NSCREATE", "NSDROP", "NSFLUSH", "NSLIST", "NSSTATS", "INFO", "SLOWLOG",
+    "SLOWLOGRESET", "HOTKEYS", "OTHER",
+];
+
+/// Reply kinds, as labelled in the metrics.
+pub const REPLY_KINDS: [&str; 4] = ["OK", "STALE", "MISS", "ERR"];
+
+/// How long a scrape connection may take to send its whole request head.
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+/// Largest request head accepted from a scraper.
+const MAX_HEAD_BYTES: usize = 8 * 1024;
+/// Connections served at once; further ones are closed unanswered.
+const MAX_SCRAPERS: usize = 8;
+
+/// Latency histogram bucket upper bounds, in microseconds.
+const BUCKETS_US: [u64; 13] = [50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 1_000_000];
+
+/// Label index of the command in a request (after any `NS` prefix).
+pub fn command_index(frames: &[zmq::Message]) -> usize {
+    let cmd = match frames.first() {
+        Some(f) if &f[..] == b"NS" => frames.get(2),
+        f => f,
+    };
+    let other = COMMANDS.len() - 1;
+    cmd.and_then(|c| COMMANDS[..other].iter().position(|name| name.as_bytes() == &c[..]))
+        .unwrap_or(other)
+}
+
+pub fn reply_kind(reply: &Reply) -> usize {
+    match reply {
+        Reply::Stale => 1,
+        Reply::Miss => 2,
+        Reply::Err(_) => 3,
+        _ => 0,
+    }
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [AtomicU64; BUCKETS_US.len() + 1],
+    sum_ns: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, d: Duration) {
+        let us = d.as_micros() as u64;
+        let i = BUCKETS_US.iter().position(|&b| us <= b).unwrap_or(BUCKETS_US.len());
+        self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        self.sum_ns.fetch_add(d.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn count(&self) -> u64 {
+        self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).sum()
+    }
+}
+
+/// Per-worker time spent handling requests.
+#[derive(Default)]
+pub struct WorkerStats {
+    busy_ns: AtomicU64,
+}
+
+impl WorkerStats {
+    pub fn add_busy(&self, d: Duration) {
+        self.busy_ns.fetch_add(d.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+pub struct Metrics {
+    started: Instant,
+    /// Indexed by `command_index * REPLY_KINDS.len() + reply_kind`.
+    latency: Vec<Histogram>,
+    workers: RwLock<Vec<Arc<WorkerStats>>>,
+    /// Requests forwarded to the workers but not picked up yet (kvz-router).
+    queued: AtomicI64,
+    /// Open client connections, if [`watch_connections`] is running.
+    connections: AtomicI64,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            started: Instant::now(),
+            latency: (0..COMMANDS.len() * REPLY_KINDS.len()).map(|_| Histogram::default()).collect(),
+            workers: RwLock::new(Vec::new()),
+            queued: AtomicI64::new(0),
+            connections: AtomicI64::new(0),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn observe(&self, command: usize, kind: usize, latency: Duration) {
+        self.latency[command * REPLY_KINDS.len() + kind].observe(latency);
+    }
+
+    /// Requests handled so far for one command and reply kind.
+    pub fn count(&self, command: usize, kind: usize) -> u64 {
+        self.latency[command * REPLY_KINDS.len() + kind].count()
+    }
+
+    /// Stats slot for worker `id`, created on first use.
+    pub fn worker(&self, id: usize) -> Arc<WorkerStats> {
+        let mut workers = self.workers.write().unwrap_or_else(|e| e.into_inner());
+        while workers.len() <= id {
+            workers.push(Arc::default());
+        }
+        Arc::clone(&workers[id])
+    }
+
+    pub fn enqueued(&self) {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dequeued(&self) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn queue_depth(&self) -> u64 {
+        self.queued.load(Ordering::Relaxed).max(0) as u64
+    }
+
+    pub fn connected_clients(&self) -> u64 {
+        self.connections.load(Ordering::Relaxed).max(0) as u64
+    }
+
+    /// Requests handled so far for one command, over all reply kinds.
+    pub fn command_total(&self, command: usize) -> u64 {
+        (0..REPLY_KINDS.len()).map(|k| self.count(command, k)).sum()
+    }
+
+    fn busy_ns(&self) -> Vec<u64> {
+        let workers = self.workers.read().unwrap_or_else(|e| e.into_inner());
+        workers.iter().map(|w| w.busy_ns.load(Ordering::Relaxed)).collect()
+    }
+}
+
+/// Escape a label value (namespace names are client-chosen).
+fn escape_label(v: &str) -> String {
+    v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Render all metrics in the Prometheus text exposition format.
+pub fn render(server: &Server) -> String {
+    let m = &server.metrics;
+    let mut out = String::new();
+
+    out.push_str("# HELP kvz_uptime_seconds Seconds since the server started.\n");
+    out.push_str("# TYPE kvz_uptime_seconds gauge\n");
+    let _ = writeln!(out, "kvz_uptime_seconds {:.3}", m.uptime().as_secs_f64());
+
+    out.push_str("# HELP kvz_requests_total Requests handled, by command and reply kind.\n");
+    out.push_str("# TYPE kvz_requests_total counter\n");
+    for (ci, cmd) in COMMANDS.iter().enumerate() {
+        for (ki, kind) in REPLY_KINDS.iter().enumerate() {
+            let n = m.count(ci, ki);
+            if n > 0 {
+                let _ = writeln!(out, "kvz_requests_total{{command=\"{cmd}\",reply=\"{kind}\"}} {n}");
+            }
+        }
+    }
+
+    out.push_str("# HELP kvz_request_duration_seconds Time from receiving a request to sending its reply.\n");
+    out.push_str("# TYPE kvz_request_duration_seconds histogram\n");
+    for (ci, cmd) in COMMANDS.iter().enumerate() {
+        for (ki, kind) in REPLY_KINDS.iter().enumerate() {
+            let h = &m.latency[ci * REPLY_KINDS.len() + ki];
+            if h.count() == 0 {
+                continue;
+            }
+            let labels = format!("command=\"{cmd}\",reply=\"{kind}\"");
+            let mut cumulative = 0;
+            for (i, bound) in BUCKETS_US.iter().enumerate() {
+                cumulative += h.buckets[i].load(Ordering::Relaxed);
+                let le = *bound as f64 / 1e6;
+                let _ = writeln!(out, "kvz_request_duration_seconds_bucket{{{labels},le=\"{le}\"}} {cumulative}");
+            }
+            // Cumulate from the same loads so the buckets stay consistent.
+            let total = cumulative + h.buckets[BUCKETS_US.len()].load(Ordering::Relaxed);
+            let _ = writeln!(out, "kvz_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {total}");
+            let sum = h.sum_ns.load(Ordering::Relaxed) as f64 / 1e9;
+            let _ = writeln!(out, "kvz_request_duration_seconds_sum{{{labels}}} {sum}");
+            let _ = writeln!(out, "kvz_request_duration_seconds_count{{{labels}}} {total}");
+        }
+    }
+
+    let namespaces = server.namespaces.list().unwrap_or_default();
+    out.push_str("# HELP kvz_shard_keys Keys stored per shard.\n");
+    out.push_str("# TYPE kvz_shard_keys gauge\n");
+    let mut shard_bytes = String::new();
+    for (name, ns) in &namespaces {
+        let name = escape_label(name);
+        for (i, (keys, bytes)) in ns.store.shard_stats().unwrap_or_default().into_iter().enumerate() {
+            let _ = writeln!(out, "kvz_shard_keys{{namespace=\"{name}\",shard=\"{i}\"}} {keys}");
+            let _ = writeln!(shard_bytes, "kvz_shard_bytes{{namespace=\"{name}\",shard=\"{i}\"}} {bytes}");
+        }
+    }
+    out.push_str("# HELP kvz_shard_bytes Estimated bytes stored per shard (keys, values, overhead).\n");
+    out.push_str("# TYPE kvz_shard_bytes gauge\n");
+    out.push_str(&shard_bytes);
+
+    out.push_str("# HELP kvz_worker_busy_seconds_total Time each worker spent handling requests.\n");
+    out.push_str("# TYPE kvz_worker_busy_seconds_total counter\n");
+    for (i, ns) in m.busy_ns().iter().enumerate() {
+        let _ = writeln!(out, "kvz_worker_busy_seconds_total{{worker=\"{i}\"}} {}", *ns as f64 / 1e9);
+    }
+
+    out.push_str("# HELP kvz_queue_depth Requests waiting for a free worker.\n");
+    out.push_str("# TYPE kvz_queue_depth gauge\n");
+    let _ = writeln!(out, "kvz_queue_depth {}", m.queue_depth());
+
+    out.push_str("# HELP kvz_connected_clients Open client connections.\n");
+    out.push_str("# TYPE kvz_connected_clients gauge\n");
+    let _ = writeln!(out, "kvz_connected_clients {}", m.connected_clients());
+    out
+}
+
+/// `INFO` fields whose values are lists, even of one element.
+pub const INFO_LISTS: [&str; 2] = ["shard_keys", "shard_bytes"];
+
+/// `(name, value)` pairs for the `INFO` reply. Values are text; lists (the
+/// fields in [`INFO_LISTS`]) are space-separated. Shard figures are summed
+/// over namespaces, which all use the same shard count and hash.
+pub fn info(server: &Server) -> Vec<(String, String)> {
+    let m = &server.metrics;
+    let mut out = Vec::new();
+    let mut add = |name: &str, value: String| out.push((name.to_string(), value));
+    add("version", env!("CARGO_PKG_VERSION").to_string());
+    add("uptime_secs", m.uptime().as_secs().to_string());
+    add("connected_clients", m.connected_clients().to_string());
+    add("workers", m.busy_ns().len().to_string());
+    add("queue_depth", m.queue_depth().to_string());
+    if let Some(monitor) = &server.monitor {
+        add("monitor_dropped", monitor.dropped().to_string());
+    }
+
+    let namespaces = server.namespaces.list().unwrap_or_default();
+    let mut shard_keys: Vec<usize> = Vec::new();
+    let mut shard_bytes: Vec<usize> = Vec::new();
+    for (_, ns) in &namespaces {
+        for (i, (keys, bytes)) in ns.store.shard_stats().unwrap_or_default().into_iter().enumerate() {
+            if i == shard_keys.len() {
+                shard_keys.push(0);
+                shard_bytes.push(0);
+            }
+            shard_keys[i] += keys;
+            shard_bytes[i] += bytes;
+        }
+    }
+    let keys: usize = namespaces.iter().map(|(_, ns)| ns.store.len()).sum();
+    let bytes: usize = namespaces.iter().map(|(_, ns)| ns.store.bytes()).sum();
+    let join = |v: &[usize]| v.iter().map(usize::to_string).collect::<Vec<_>>().join(" ");
+    add("namespaces", namespaces.len().to_string());
+    add("keys", keys.to_string());
+    add("memory_bytes", bytes.to_string());
+    add("shards", shard_keys.len().to_string());
+    add("shard_keys", join(&shard_keys));
+    add("shard_bytes", join(&shard_bytes));
+    // Fullest shard relative to an even spread; 1.0 is perfect balance.
+    let max = shard_keys.iter().copied().max().unwrap_or(0);
+    let skew = if keys == 0 { 0.0 } else { max as f64 * shard_keys.len() as f64 / keys as f64 };
+    add("shard_skew", format!("{skew:.2}"));
+
+    let totals: Vec<u64> = (0..COMMANDS.len()).map(|c| m.command_total(c)).collect();
+    let errors: u64 = (0..COMMANDS.len()).map(|c| m.count(c, REPLY_KINDS.len() - 1)).sum();
+    add("ops_total", totals.iter().sum::<u64>().to_string());
+    add("errors_total", errors.to_string());
+    for (cmd, n) in COMMANDS.iter().zip(&totals) {
+        if *n > 0 {
+            add(&format!("ops_{}", cmd.to_lowercase()), n.to_string());
+        }
+    }
+    out
+}
+
+/// Count connections accepted on `sock` (and dropped again) in the server's
+/// metrics, from ZMQ socket monitor events handled on a background thread.
+pub fn watch_connections(ctx: &zmq::Context, sock: &zmq::Socket, server: Arc<Server>) -> Result<()> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let endpoint = format!("inproc://kvz-monitor-{}", NEXT.fetch_add(1, Ordering::Relaxed));
+    let events = zmq::SocketEvent::ACCEPTED.to_raw() | zmq::SocketEvent::DISCONNECTED.to_raw();
+    sock.monitor(&endpoint, events as i32).context("monitor socket")?;
+    let mon = ctx.socket(zmq::PAIR)?;
+    mon.connect(&endpoint)?;
+    thread::spawn(move || {
+        // Each event is [event(2B) value(4B), endpoint], in native byte order.
+        while let Ok(frames) = mon.recv_multipart(0) {
+            let Some(ev) = frames.first().and_then(|f| f.get(..2)) else {
+                continue;
+            };
+            let delta = match u16::from_ne_bytes([ev[0], ev[1]]) {
+                e if e == zmq::SocketEvent::ACCEPTED.to_raw() => 1,
+                e if e == zmq::SocketEvent::DISCONNECTED.to_raw() => -1,
+                _ => 0,
+            };
+            server.metrics.connections.fetch_add(delta, Ordering::Relaxed);
+        }
+    });
+    Ok(())
+}
+
+/// Read up to the blank line ending the request head, within
+/// [`READ_TIMEOUT`] overall (a per-read timeout would let a client trickle
+/// bytes forever) and [`MAX_HEAD_BYTES`].
+fn read_head(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let deadline = Instant::now() + READ_TIMEOUT;
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") && !head.windows(2).any(|w| w == b"\n\n") {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "request head not received in time"));
+        }
+        stream.set_read_timeout(Some(left))?;
+        let n = Read::by_ref(stream).take((MAX_HEAD_BYTES - head.len()) as u64).read(&mut buf)?;
+        if n == 0 {
+            if head.len() == MAX_HEAD_BYTES {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "request head too large"));
+            }
+            break;
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    Ok(head)
+}
+
+fn respond(mut stream: TcpStream, server: &Server) -> io::Result<()> {
+    // There is no body to read for GET; the headers are ignored.
+    let head = read_head(&mut stream)?;
+    let request_line = String::from_utf8_lossy(head.split(|&b| b == b'\n').next().unwrap_or(&[]));
+    if !request_line.starts_with("GET ") {
+        return stream.write_all(b"HTTP/1.0 405 Method Not Allowed\r\nContent-Length: 0\r\n\r\n");
+    }
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+    if path != "/metrics" && path != "/" {
+        return stream.write_all(b"HTTP/1.0 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+    }
+    let body = render(server);
+    write!(
+        stream,
+        "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(body.as_bytes())
+}
+
+/// `--metrics-bind`.
+#[derive(Args, Debug, Clone, Default)]
+pub struct MetricsArgs {
+    /// Serve Prometheus metrics over HTTP on this address, e.g. 127.0.0.1:9100
+    #[arg(long, value_name = "ADDR")]
+    pub metrics_bind: Option<String>,
+}
+
+impl MetricsArgs {
+    /// Start the metrics listener, if configured. Returns the bound address.
+    pub fn start(&self, server: Arc<Server>) -> Result<Option<SocketAddr>> {
+        let Some(bind) = &self.metrics_bind else {
+            return Ok(None);
+        };
+        let listener = TcpListener::bind(bind).with_context(|| format!("bind metrics listener {bind}"))?;
+        let addr = listener.local_addr()?;
+        let active = Arc::new(AtomicUsize::new(0));
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                // A slow or idle client must not hold up the other scrapers,
+                // but the number of threads they can tie up is bounded.
+                if active.fetch_add(1, Ordering::Relaxed) >= MAX_SCRAPERS {
+                    active.fetch_sub(1, Ordering::Relaxed);
+                    debug!("too many metrics connections, closing one");
+                    continue;
+                }
+                let (server, active) = (server.clone(), active.clone());
+                thread::spawn(move || {
+                    if let Err(e) = respond(stream, &server) {
+                        debug!(error = %e, "metrics request failed");
+                    }
+                    active.fetch_sub(1, Ordering::Relaxed);
+                });
+            }
+        });
+        Ok(Some(addr))
+    }
+}
@@ -0,0 +1,162 @@
+//! Live request stream (`--monitor-bind`, `kvz monitor`).
+//!
+//! Every handled request is published on an XPUB socket as one multipart
+//! message, topic first so subscribers can filter by command:
+//!
+//! ```text
+//! [command, time_us(8B BE), worker(8B BE), duration_us(8B BE), client,
+//!  namespace, key, request_bytes(8B BE), reply_kind, reply_bytes(8B BE), value]
+//! ```
+//!
+//! `value` holds at most `--monitor-value-bytes` bytes of the value written
+//! or read and is empty (redacted) by default. Workers hand events to a
+//! publisher thread through a bounded queue and drop them when it is full;
+//! while nobody is subscribed they skip the work altogether. ACLs do not
+//! apply to the stream, so bind it to a private address or protect it with
+//! CURVE and an allowlist: `--monitor-bind` takes the same `curve=` and
+//! `allowlist=` options as `--bind` (see [`crate::listen`]).
+
+use crate::listen::{Bound, Listeners};
+use crate::metrics::{reply_kind, COMMANDS, REPLY_KINDS};
+use crate::server::{Reply, Summary};
+use anyhow::{Context, Result};
+use clap::Args;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Events queued for the publisher thread before workers start dropping them.
+const QUEUE_LEN: usize = 4096;
+
+/// Frames per monitor event.
+pub const EVENT_FRAMES: usize = 11;
+
+pub struct Monitor {
+    /// Some subscriber is listening.
+    active: Arc<AtomicBool>,
+    sample: f64,
+    value_bytes: usize,
+    tx: SyncSender<Vec<Vec<u8>>>,
+    dropped: AtomicU64,
+}
+
+impl Monitor {
+    /// Whether to report the next request: someone is listening and the
+    /// request is picked by `--monitor-sample`.
+    pub fn wants(&self) -> bool {
+        self.active.load(Ordering::Relaxed) && (self.sample >= 1.0 || rand::random::<f64>() < self.sample)
+    }
+
+    /// Value bytes to capture per request.
+    pub fn value_bytes(&self) -> usize {
+        self.value_bytes
+    }
+
+    /// Events lost because the publisher fell behind.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn publish(&self, req: &Summary, reply: &Reply, worker: usize, duration: Duration) {
+        let n = |v: u64| v.to_be_bytes().to_vec();
+        let time_us = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_micros() as u64);
+        let value = if req.value_size > 0 { req.value.clone() } else { self.reply_value(req.command, reply) };
+        let event = vec![
+            COMMANDS[req.command].as_bytes().to_vec(),
+            n(time_us),
+            n(worker as u64),
+            n(duration.as_micros() as u64),
+            req.client.as_bytes().to_vec(),
+            req.namespace.as_bytes().to_vec(),
+            req.key.clone(),
+            n(req.value_size as u64),
+            REPLY_KINDS[reply_kind(reply)].as_bytes().to_vec(),
+            n(reply.payload_len() as u64),
+            value,
+        ];
+        if self.tx.try_send(event).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The start of the value a read returned, up to `value_bytes`.
+    fn reply_value(&self, command: usize, reply: &Reply) -> Vec<u8> {
+        if self.value_bytes == 0 {
+            return Vec::new();
+        }
+        let data: &[u8] = match (COMMANDS[command], reply) {
+            (_, Reply::Value { data, .. }) => data,
+            ("GETRANGE" | "GETBEGIN" | "GETCHUNK", Reply::Frames(frames)) => frames.last().map_or(&[], Vec::as_slice),
+            _ => &[],
+        };
+        data[..data.len().min(self.value_bytes)].to_vec()
+    }
+}
+
+/// Send queued events and track subscriptions until the server goes away.
+fn publish_loop(sock: zmq::Socket, rx: Receiver<Vec<Vec<u8>>>, active: Arc<AtomicBool>) {
+    // XPUB reports the first subscription to each topic and the last
+    // unsubscription from it, so this counts topics with listeners.
+    let mut topics = 0usize;
+    loop {
+        match rx.recv_timeout(Duration::from_millis(50)) {
+            Ok(event) => {
+                // PUB never blocks; it drops messages for slow subscribers.
+                let _ = sock.send_multipart(event, zmq::DONTWAIT);
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        while let Ok(msg) = sock.recv_bytes(zmq::DONTWAIT) {
+            match msg.first() {
+                Some(1) => topics += 1,
+                Some(0) => topics = topics.saturating_sub(1),
+                _ => {}
+            }
+        }
+        active.store(topics > 0, Ordering::Relaxed);
+    }
+}
+
+/// `--monitor-bind`, `--monitor-sample`, `--monitor-value-bytes`.
+#[derive(Args, Debug, Clone, Default)]
+pub struct MonitorArgs {
+    /// Stream handled requests on a PUB socket at this endpoint (see `kvz monitor`),
+    /// ENDPOINT[,curve=FILE|none][,allowlist=FILE|none]
+    #[arg(long, value_name = "ENDPOINT")]
+    pub monitor_bind: Option<String>,
+    /// Fraction of requests to stream, 0.0–1.0
+    #[arg(long, value_name = "RATE", default_value_t = 1.0)]
+    pub monitor_sample: f64,
+    /// Include up to this many bytes of each value (default: values redacted)
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub monitor_value_bytes: usize,
+}
+
+impl MonitorArgs {
+    /// Bind the monitor socket, if configured, with the security `listeners`
+    /// resolved for it. Returns the monitor and the bound endpoint.
+    pub fn start(&self, ctx: &zmq::Context, listeners: &Listeners) -> Result<Option<(Monitor, Bound)>> {
+        if self.monitor_bind.is_none() {
+            return Ok(None);
+        }
+        let sock = ctx.socket(zmq::XPUB)?;
+        let Some(bound) = listeners.bind_monitor(&sock).context("bind monitor socket")? else {
+            return Ok(None);
+        };
+        let active = Arc::new(AtomicBool::new(false));
+        let (tx, rx) = sync_channel(QUEUE_LEN);
+        let flag = Arc::clone(&active);
+        thread::spawn(move || publish_loop(sock, rx, flag));
+        let monitor = Monitor {
+            active,
+            sample: self.monitor_sample.clamp(0.0, 1.0),
+            value_bytes: self.monitor_value_bytes,
+            tx,
+            dropped: AtomicU64::new(0),
+        };
+        Ok(Some((monitor, bound)))
+    }
+}
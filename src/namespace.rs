@@ -0,0 +1,133 @@
+//! Named namespaces: independent key spaces inside one server.
+//!
+//! Each namespace is its own [`ShardedStore`] with its own counters and an
+//! optional byte quota, so it can be flushed or dropped without touching the
+//! others. Requests without a namespace use [`DEFAULT_NAMESPACE`], which
+//! always exists.
+
+use crate::protocol::{ErrorCode, KvzError};
+use crate::store::ShardedStore;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+pub const DEFAULT_NAMESPACE: &str = "default";
+/// Longest accepted namespace name, in bytes.
+pub const MAX_NAME_LEN: usize = 64;
+/// Default for `--max-namespaces`.
+pub const DEFAULT_MAX_NAMESPACES: usize = 1024;
+
+/// Per-namespace operation counters.
+#[derive(Default)]
+pub struct NsStats {
+    pub gets: AtomicU64,
+    pub hits: AtomicU64,
+    pub puts: AtomicU64,
+    pub stale: AtomicU64,
+}
+
+pub struct Namespace {
+    pub name: String,
+    pub store: ShardedStore,
+    pub stats: NsStats,
+    /// Byte quota (see [`ShardedStore::bytes`]); 0 means none.
+    quota: AtomicU64,
+}
+
+impl Namespace {
+    fn new(name: &str, shards: usize, quota: u64) -> Namespace {
+        Namespace {
+            name: name.to_string(),
+            store: ShardedStore::new(shards),
+            stats: NsStats::default(),
+            quota: AtomicU64::new(quota),
+        }
+    }
+
+    pub fn quota(&self) -> Option<usize> {
+        match self.quota.load(Ordering::Relaxed) {
+            0 => None,
+            q => Some(usize::try_from(q).unwrap_or(usize::MAX)),
+        }
+    }
+}
+
+pub struct Namespaces {
+    shards: usize,
+    /// Most namespaces that may exist at once, counting the default one.
+    pub max: usize,
+    map: RwLock<HashMap<String, Arc<Namespace>>>,
+}
+
+fn poisoned() -> KvzError {
+    KvzError::new(ErrorCode::StoreError, "namespace table poisoned")
+}
+
+fn check_name(name: &str) -> Result<(), KvzError> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(KvzError::new(
+            ErrorCode::BadArgument,
+            format!("namespace names are 1 to {MAX_NAME_LEN} bytes"),
+        ));
+    }
+    Ok(())
+}
+
+impl Namespaces {
+    /// Namespaces with `shards` shards each, starting with just the default one.
+    pub fn new(shards: usize) -> Namespaces {
+        let mut map = HashMap::new();
+        map.insert(DEFAULT_NAMESPACE.to_string(), Arc::new(Namespace::new(DEFAULT_NAMESPACE, shards, 0)));
+        Namespaces { shards, max: DEFAULT_MAX_NAMESPACES, map: RwLock::new(map) }
+    }
+
+    pub fn get(&self, name: &str) -> Result<Arc<Namespace>, KvzError> {
+        let map = self.map.read().map_err(|_| poisoned())?;
+        map.get(name)
+            .cloned()
+            .ok_or_else(|| KvzError::new(ErrorCode::NoNamespace, format!("no namespace {name:?}")))
+    }
+
+    /// Create `name`, or update its quota if it already exists (0 = none).
+    /// Creating one beyond [`Namespaces::max`] is `OVER_LIMIT`.
+    pub fn create(&self, name: &str, quota: u64) -> Result<(), KvzError> {
+        check_name(name)?;
+        let mut map = self.map.write().map_err(|_| poisoned())?;
+        match map.get(name) {
+            Some(ns) => ns.quota.store(quota, Ordering::Relaxed),
+            None => {
+                if map.len() >= self.max {
+                    return Err(KvzError::new(
+                        ErrorCode::OverLimit,
+                        format!("namespace limit reached ({} namespaces)", self.max),
+                    ));
+                }
+                map.insert(name.to_string(), Arc::new(Namespace::new(name, self.shards, quota)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove `name` from the table, close its store and return it for the
+    /// caller to clear. Requests already holding it still read, but their
+    /// writes are refused; the default namespace can only be flushed.
+    pub fn drop(&self, name: &str) -> Result<Arc<Namespace>, KvzError> {
+        if name == DEFAULT_NAMESPACE {
+            return Err(KvzError::new(ErrorCode::BadArgument, "the default namespace cannot be dropped"));
+        }
+        let mut map = self.map.write().map_err(|_| poisoned())?;
+        let ns = map
+            .remove(name)
+            .ok_or_else(|| KvzError::new(ErrorCode::NoNamespace, format!("no namespace {name:?}")))?;
+        ns.store.close();
+        Ok(ns)
+    }
+
+    /// All namespaces, sorted by name.
+    pub fn list(&self) -> Result<Vec<(String, Arc<Namespace>)>, KvzError> {
+        let map = self.map.read().map_err(|_| poisoned())?;
+        let mut all: Vec<_> = map.iter().map(|(k, v)| (k.clone(), Arc::clone(v))).collect();
+        all.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(all)
+    }
+}
@@ -0,0 +1,86 @@
+//! Immutable, reference-counted value payloads that can be sent without copying.
+//!
+//! Large PUT frames are retained as the `zmq::Message` they arrived in, and GET
+//! replies hand ZeroMQ a pointer into the shared buffer plus an `Arc` that is
+//! released once the frame has left the socket. Small payloads are plain heap
+//! buffers: received frames below [`ZERO_COPY_MIN`] may point into libzmq's
+//! shared receive batch, and retaining them would pin the whole batch.
+
+use anyhow::Result;
+use std::ops::Deref;
+use std::os::raw::c_void;
+use std::sync::Arc;
+
+/// Payloads at least this large are retained and sent zero-copy.
+pub const ZERO_COPY_MIN: usize = 16 * 1024;
+
+pub enum Payload {
+    Heap(Vec<u8>),
+    Frame(zmq::Message),
+}
+
+impl Payload {
+    /// Keep a received frame, copying it only if it is small.
+    pub fn from_frame(msg: zmq::Message) -> Payload {
+        if msg.len() >= ZERO_COPY_MIN {
+            Payload::Frame(msg)
+        } else {
+            Payload::Heap(msg.to_vec())
+        }
+    }
+}
+
+impl From<Vec<u8>> for Payload {
+    fn from(v: Vec<u8>) -> Payload {
+        Payload::Heap(v)
+    }
+}
+
+impl Deref for Payload {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        match self {
+            Payload::Heap(v) => v,
+            Payload::Frame(m) => m,
+        }
+    }
+}
+
+/// ZeroMQ free callback: drops the `Arc` leaked in [`send_payload`].
+unsafe extern "C" fn release_payload(_data: *mut c_void, hint: *mut c_void) {
+    // SAFETY: `hint` came from `Arc::into_raw` in `send_payload` and is released exactly once.
+    drop(unsafe { Arc::from_raw(hint as *const Payload) });
+}
+
+/// Send `data` as one frame. Large payloads are not copied: the frame borrows
+/// the shared buffer and keeps it alive until ZeroMQ is done with it.
+pub fn send_payload(sock: &mut zmq::Socket, data: &Arc<Payload>, flags: i32) -> Result<()> {
+    if data.len() < ZERO_COPY_MIN {
+        sock.send(&data[..], flags)?;
+        return Ok(());
+    }
+    let hint = Arc::into_raw(Arc::clone(data));
+    let mut msg = zmq_sys::zmq_msg_t::default();
+    // SAFETY: the buffer stays valid while `hint` holds a reference; libzmq only
+    // reads it and calls `release_payload` once when the frame is freed.
+    unsafe {
+        let rc = zmq_sys::zmq_msg_init_data(
+            &mut msg,
+            data.as_ptr() as *mut c_void,
+            data.len(),
+            Some(release_payload),
+            hint as *mut c_void,
+        );
+        if rc == -1 {
+            drop(Arc::from_raw(hint));
+            return Err(zmq::Error::from_raw(zmq_sys::zmq_errno()).into());
+        }
+        if zmq_sys::zmq_msg_send(&mut msg, sock.as_mut_ptr(), flags) == -1 {
+            let err = zmq::Error::from_raw(zmq_sys::zmq_errno());
+            // Not sent, so we still own the frame; closing it runs the free callback.
+            zmq_sys::zmq_msg_close(&mut msg);
+            return Err(err.into());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,159 @@
+//! Wire protocol helpers shared by both servers and the clients.
+//!
+//! Errors are replied as `["ERR", msg, code(2B BE)]`. The message stays in the
+//! second frame so older clients keep working; the numeric code is what clients
+//! should match on. `THROTTLED` errors add a fourth frame, the suggested
+//! retry delay in milliseconds (8B BE).
+
+use anyhow::Result;
+use std::fmt;
+
+/// Stable numeric error codes. Never renumber; only append.
+#[repr(u16)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// Missing or unrecognised code (e.g. reply from an older server)
+    Unknown = 0,
+    /// Request had no frames at all
+    EmptyMessage = 1,
+    /// Wrong number of frames for the command
+    BadFrameCount = 2,
+    /// Timestamp frame is not 8 bytes
+    BadTimestamp = 3,
+    /// Key is not valid UTF-8 (no longer sent: keys are raw bytes)
+    KeyNotUtf8 = 4,
+    /// First frame is not a known command
+    UnknownCommand = 5,
+    /// Store failure (e.g. poisoned shard lock)
+    StoreError = 6,
+    /// Request exceeds a configured limit
+    OverLimit = 7,
+    /// Key longer than `--max-key-bytes`
+    KeyTooLarge = 8,
+    /// Value larger than `--max-value-bytes`
+    ValueTooLarge = 9,
+    /// Request larger than `--max-msg-bytes`
+    MessageTooLarge = 10,
+    /// Chunked upload/download session unknown or expired
+    NoSession = 11,
+    /// Malformed argument frame (session id, length, ...)
+    BadArgument = 12,
+    /// Client identity lacks permission for the key (`--acl`)
+    Denied = 13,
+    /// Namespace does not exist
+    NoNamespace = 14,
+    /// Write would exceed a storage quota
+    QuotaExceeded = 15,
+    /// Client exceeded its request or byte rate; retry after the hint
+    Throttled = 16,
+}
+
+impl ErrorCode {
+    pub fn from_u16(v: u16) -> ErrorCode {
+        match v {
+            1 => ErrorCode::EmptyMessage,
+            2 => ErrorCode::BadFrameCount,
+            3 => ErrorCode::BadTimestamp,
+            4 => ErrorCode::KeyNotUtf8,
+            5 => ErrorCode::UnknownCommand,
+            6 => ErrorCode::StoreError,
+            7 => ErrorCode::OverLimit,
+            8 => ErrorCode::KeyTooLarge,
+            9 => ErrorCode::ValueTooLarge,
+            10 => ErrorCode::MessageTooLarge,
+            11 => ErrorCode::NoSession,
+            12 => ErrorCode::BadArgument,
+            13 => ErrorCode::Denied,
+            14 => ErrorCode::NoNamespace,
+            15 => ErrorCode::QuotaExceeded,
+            16 => ErrorCode::Throttled,
+            _ => ErrorCode::Unknown,
+        }
+    }
+
+    /// Short upper-case name, e.g. `BAD_FRAME_COUNT`
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorCode::Unknown => "UNKNOWN",
+            ErrorCode::EmptyMessage => "EMPTY_MESSAGE",
+            ErrorCode::BadFrameCount => "BAD_FRAME_COUNT",
+            ErrorCode::BadTimestamp => "BAD_TIMESTAMP",
+            ErrorCode::KeyNotUtf8 => "KEY_NOT_UTF8",
+            ErrorCode::UnknownCommand => "UNKNOWN_COMMAND",
+            ErrorCode::StoreError => "STORE_ERROR",
+            ErrorCode::OverLimit => "OVER_LIMIT",
+            ErrorCode::KeyTooLarge => "KEY_TOO_LARGE",
+            ErrorCode::ValueTooLarge => "VALUE_TOO_LARGE",
+            ErrorCode::MessageTooLarge => "MESSAGE_TOO_LARGE",
+            ErrorCode::NoSession => "NO_SESSION",
+            ErrorCode::BadArgument => "BAD_ARGUMENT",
+            ErrorCode::Denied => "DENIED",
+            ErrorCode::NoNamespace => "NO_NAMESPACE",
+            ErrorCode::QuotaExceeded => "QUOTA_EXCEEDED",
+            ErrorCode::Throttled => "THROTTLED",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name(), *self as u16)
+    }
+}
+
+/// Error reply: built by the servers, decoded by the clients.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KvzError {
+    pub code: ErrorCode,
+    pub msg: String,
+    /// Retry hint sent with `THROTTLED`.
+    pub retry_after_ms: Option<u64>,
+}
+
+impl KvzError {
+    pub fn new(code: ErrorCode, msg: impl Into<String>) -> Self {
+        KvzError { code, msg: msg.into(), retry_after_ms: None }
+    }
+
+    pub fn throttled(msg: impl Into<String>, retry_after_ms: u64) -> Self {
+        KvzError { code: ErrorCode::Throttled, msg: msg.into(), retry_after_ms: Some(retry_after_ms) }
+    }
+}
+
+impl fmt::Display for KvzError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code.name(), self.msg)
+    }
+}
+
+impl std::error::Error for KvzError {}
+
+/// Decode an 8-byte big-endian u64 frame (timestamps, sizes).
+pub fn read_u64(frame: &[u8]) -> Option<u64> {
+    let b: [u8; 8] = frame.try_into().ok()?;
+    Some(u64::from_be_bytes(b))
+}
+
+/// Send `["ERR", msg, code]`, plus `retry_after_ms` if set.
+pub fn send_err(sock: &zmq::Socket, err: &KvzError) -> Result<()> {
+    let codeb = (err.code as u16).to_be_bytes();
+    match err.retry_after_ms {
+        Some(ms) => sock.send_multipart([b"ERR".as_slice(), err.msg.as_bytes(), &codeb, &ms.to_be_bytes()], 0)?,
+        None => sock.send_multipart([b"ERR".as_slice(), err.msg.as_bytes(), &codeb], 0)?,
+    }
+    Ok(())
+}
+
+/// Decode an `ERR` reply. Tolerates a missing code frame (older servers).
+pub fn parse_err(rep: &[Vec<u8>]) -> KvzError {
+    let msg = rep
+        .get(1)
+        .map(|b| String::from_utf8_lossy(b).into_owned())
+        .unwrap_or_default();
+    let code = match rep.get(2) {
+        Some(b) if b.len() == 2 => ErrorCode::from_u16(u16::from_be_bytes([b[0], b[1]])),
+        _ => ErrorCode::Unknown,
+    };
+    let retry_after_ms = rep.get(3).and_then(|b| read_u64(b));
+    KvzError { code, msg, retry_after_ms }
+}
@@ -0,0 +1,233 @@
+//! Per-client rate limits and per-prefix storage quotas.
+//!
+//! Rate limits are token buckets keyed by client: the authenticated identity
+//! if there is one, otherwise the peer address, so reconnecting does not
+//! start a fresh bucket. One bucket counts requests, the other
+//! counts request plus reply payload bytes; a client over either limit gets
+//! `THROTTLED` with the time until it may retry. A request is admitted once
+//! the byte bucket holds its size, or a full bucket if it is larger than that.
+//! Anything beyond the bucket, and reply bytes, which are charged after the
+//! fact, leave the bucket in debt: the client is throttled until the rate has
+//! paid it back.
+//!
+//! Prefix quotas cap the stored bytes (as accounted by the store) under a key
+//! prefix, summed over all namespaces. Namespace quotas live in
+//! [`crate::namespace`].
+
+use crate::namespace::DEFAULT_MAX_NAMESPACES;
+use crate::protocol::KvzError;
+use anyhow::{anyhow, Context, Result};
+use clap::Args;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// At most this many clients are tracked; beyond it the least recently seen
+/// are dropped, [`EVICT_BATCH`] at a time.
+const MAX_TRACKED_CLIENTS: usize = 65536;
+const EVICT_BATCH: usize = MAX_TRACKED_CLIENTS / 16;
+
+struct Bucket {
+    tokens: f64,
+}
+
+impl Bucket {
+    fn refill(&mut self, rate: f64, cap: f64, elapsed: f64) {
+        self.tokens = (self.tokens + rate * elapsed).min(cap);
+    }
+
+    /// Seconds until the bucket holds at least `need` tokens.
+    fn wait(&self, rate: f64, need: f64) -> f64 {
+        if self.tokens >= need {
+            0.0
+        } else {
+            (need - self.tokens) / rate
+        }
+    }
+}
+
+struct ClientBuckets {
+    ops: Bucket,
+    bytes: Bucket,
+    updated: Instant,
+}
+
+pub struct RateLimiter {
+    ops_per_sec: f64,
+    bytes_per_sec: f64,
+    burst_secs: f64,
+    clients: Mutex<HashMap<String, ClientBuckets>>,
+}
+
+impl RateLimiter {
+    /// Rates of 0 mean unlimited.
+    pub fn new(ops_per_sec: f64, bytes_per_sec: f64, burst_secs: f64) -> RateLimiter {
+        RateLimiter { ops_per_sec, bytes_per_sec, burst_secs, clients: Mutex::new(HashMap::new()) }
+    }
+
+    fn ops_cap(&self) -> f64 {
+        (self.ops_per_sec * self.burst_secs).max(1.0)
+    }
+
+    fn bytes_cap(&self) -> f64 {
+        (self.bytes_per_sec * self.burst_secs).max(1.0)
+    }
+
+    /// Refill `client`'s buckets and run `f` on them.
+    fn with_buckets<T>(&self, client: &str, f: impl FnOnce(&mut ClientBuckets) -> T) -> T {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        if clients.len() >= MAX_TRACKED_CLIENTS && !clients.contains_key(client) {
+            evict_oldest(&mut clients);
+        }
+        let b = clients.entry(client.to_string()).or_insert_with(|| ClientBuckets {
+            ops: Bucket { tokens: self.ops_cap() },
+            bytes: Bucket { tokens: self.bytes_cap() },
+            updated: now,
+        });
+        let elapsed = now.duration_since(b.updated).as_secs_f64();
+        b.updated = now;
+        b.ops.refill(self.ops_per_sec, self.ops_cap(), elapsed);
+        b.bytes.refill(self.bytes_per_sec, self.bytes_cap(), elapsed);
+        f(b)
+    }
+
+    /// Admit one request of `bytes` bytes, or say how long to wait.
+    pub fn admit(&self, client: &str, bytes: usize) -> Result<(), KvzError> {
+        self.with_buckets(client, |b| {
+            let mut wait: f64 = 0.0;
+            if self.ops_per_sec > 0.0 {
+                wait = wait.max(b.ops.wait(self.ops_per_sec, 1.0));
+            }
+            if self.bytes_per_sec > 0.0 {
+                // A request larger than the bucket waits for a full bucket
+                // rather than forever.
+                let need = (bytes as f64).min(self.bytes_cap());
+                wait = wait.max(b.bytes.wait(self.bytes_per_sec, need));
+            }
+            if wait > 0.0 {
+                let ms = (wait * 1000.0).ceil().max(1.0) as u64;
+                return Err(KvzError::throttled(format!("rate limit exceeded, retry in {ms} ms"), ms));
+            }
+            b.ops.tokens -= 1.0;
+            b.bytes.tokens -= bytes as f64;
+            Ok(())
+        })
+    }
+
+    /// Charge reply bytes to `client`.
+    pub fn charge(&self, client: &str, bytes: usize) {
+        if self.bytes_per_sec > 0.0 && bytes > 0 {
+            self.with_buckets(client, |b| b.bytes.tokens -= bytes as f64);
+        }
+    }
+}
+
+/// Drop the [`EVICT_BATCH`] least recently updated clients, so the scan runs
+/// once per batch of new clients rather than for each one. The oldest buckets
+/// are normally idle and full again, so dropping them forgives nothing.
+fn evict_oldest(clients: &mut HashMap<String, ClientBuckets>) {
+    let mut by_age: Vec<(Instant, &String)> = clients.iter().map(|(k, b)| (b.updated, k)).collect();
+    let n = EVICT_BATCH.min(by_age.len());
+    if n < by_age.len() {
+        by_age.select_nth_unstable(n);
+    }
+    let oldest: Vec<String> = by_age[..n].iter().map(|(_, k)| (*k).clone()).collect();
+    for k in oldest {
+        clients.remove(&k);
+    }
+}
+
+pub struct PrefixQuota {
+    pub prefix: Vec<u8>,
+    pub limit: usize,
+    used: AtomicUsize,
+}
+
+impl PrefixQuota {
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub struct PrefixQuotas {
+    rules: Vec<PrefixQuota>,
+}
+
+impl PrefixQuotas {
+    /// Parse `PREFIX=BYTES`; the prefix may be written as `hex:<hex>`.
+    fn parse(spec: &str) -> Result<PrefixQuota> {
+        let (prefix, limit) = spec
+            .rsplit_once('=')
+            .ok_or_else(|| anyhow!("expected PREFIX=BYTES, got {spec:?}"))?;
+        let prefix = match prefix.strip_prefix("hex:") {
+            Some(h) => hex::decode(h).with_context(|| format!("invalid hex prefix in {spec:?}"))?,
+            None => prefix.as_bytes().to_vec(),
+        };
+        let limit = limit.parse().with_context(|| format!("invalid byte count in {spec:?}"))?;
+        Ok(PrefixQuota { prefix, limit, used: AtomicUsize::new(0) })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The first quota that growing `key` by `growth` bytes would exceed.
+    pub fn exceeded(&self, key: &[u8], growth: usize) -> Option<&PrefixQuota> {
+        self.rules
+            .iter()
+            .find(|q| key.starts_with(&q.prefix) && q.used() + growth > q.limit)
+    }
+
+    /// Record that the entry under `key` grew (or shrank) by `delta` bytes.
+    pub fn account(&self, key: &[u8], delta: isize) {
+        for q in self.rules.iter().filter(|q| key.starts_with(&q.prefix)) {
+            if delta >= 0 {
+                q.used.fetch_add(delta as usize, Ordering::Relaxed);
+            } else {
+                q.used.fetch_sub(delta.unsigned_abs(), Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Rate limit and quota flags shared by both servers.
+#[derive(Args, Debug, Clone, Default)]
+pub struct QuotaArgs {
+    /// Per-client request rate limit in requests/s (0 = unlimited)
+    #[arg(long, value_name = "N", default_value_t = 0.0)]
+    pub client_ops_per_sec: f64,
+    /// Per-client traffic limit in bytes/s, requests plus replies (0 = unlimited); a request
+    /// or reply larger than the burst is let through and then paid back before the next one
+    #[arg(long, value_name = "N", default_value_t = 0.0)]
+    pub client_bytes_per_sec: f64,
+    /// Burst allowance: bucket size in seconds of traffic at the limit rate
+    #[arg(long, value_name = "SECS", default_value_t = 1.0)]
+    pub client_burst_secs: f64,
+    /// Cap stored bytes under a key prefix, over all namespaces (repeatable)
+    #[arg(long = "prefix-quota", value_name = "PREFIX=BYTES")]
+    pub prefix_quotas: Vec<String>,
+    /// Most namespaces that may exist at once, including `default`
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_NAMESPACES)]
+    pub max_namespaces: usize,
+}
+
+impl QuotaArgs {
+    pub fn rate_limiter(&self) -> Option<RateLimiter> {
+        if self.client_ops_per_sec <= 0.0 && self.client_bytes_per_sec <= 0.0 {
+            return None;
+        }
+        Some(RateLimiter::new(
+            self.client_ops_per_sec.max(0.0),
+            self.client_bytes_per_sec.max(0.0),
+            self.client_burst_secs.max(0.0),
+        ))
+    }
+
+    pub fn prefix_quotas(&self) -> Result<PrefixQuotas> {
+        let rules = self.prefix_quotas.iter().map(|s| PrefixQuotas::parse(s)).collect::<Result<_>>()?;
+        Ok(PrefixQuotas { rules })
+    }
+}
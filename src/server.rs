@@ -0,0 +1,774 @@
+//! Request handling shared by `kvz server` and `kvz-router`.
+//!
+//! Protocol:
+//!   PUT:       ["PUT", key(bytes), ts(8B BE), data]
+//!   GET:       ["GET", key]
+//!   LIMITS:    ["LIMITS"]
+//!   GETRANGE:  ["GETRANGE", key, offset(8B BE), len(8B BE)]
+//! Chunked transfers (sid = session id, 8B BE):
+//!   PUTBEGIN:  ["PUTBEGIN", key, ts(8B BE)]         -> ["OK", sid]
+//!   PUTCHUNK:  ["PUTCHUNK", sid, data]              -> ["OK"]
+//!   PUTCOMMIT: ["PUTCOMMIT", sid]                   -> ["OK"] or ["STALE"]
+//!   PUTABORT:  ["PUTABORT", sid]                    -> ["OK"]
+//!   GETBEGIN:  ["GETBEGIN", key, chunk_len(8B BE)]  -> ["OK", sid, ts, total_len, first_chunk] or ["MISS"]
+//!   GETCHUNK:  ["GETCHUNK", sid, chunk_len(8B BE)]  -> ["OK", chunk]
+//!   GETEND:    ["GETEND", sid]                      -> ["OK"]
+//! A download session closes itself after its last byte; GETBEGIN returns
+//! sid 0 when the first chunk already holds the whole value. chunk_len must
+//! be at least 1 and is capped at max_value_bytes. Sessions only answer the
+//! client that opened them (see `kvz::session`).
+//! Replies:
+//!   PUT    -> ["OK"] or ["STALE"] or ["ERR", msg, code(2B BE)]
+//!   GET    -> ["OK", ts(8B BE), data] or ["MISS"] or ["ERR", msg, code(2B BE)]
+//!   LIMITS -> ["OK", max_key(8B BE), max_value(8B BE), max_msg(8B BE)]
+//!   GETRANGE -> ["OK", ts(8B BE), total_len(8B BE), bytes] or ["MISS"]
+//!     (the range is clamped to the value, so it may be short or empty)
+//! The timestamp of a chunked upload is applied atomically at PUTCOMMIT.
+//! Namespaces (see `kvz::namespace`):
+//!   NS:        ["NS", name, command, args...]  -> runs the command in namespace `name`
+//!   NSCREATE:  ["NSCREATE", name, quota(8B BE)] -> ["OK"] (quota 0 = none; updates an existing one)
+//!   NSDROP:    ["NSDROP", name]                 -> ["OK"]
+//!   NSFLUSH:   ["NSFLUSH", name]                -> ["OK", removed_keys(8B BE)]
+//!   NSLIST:    ["NSLIST"]                       -> ["OK", name...]
+//!   NSSTATS:   ["NSSTATS", name]                -> ["OK", keys, bytes, quota, gets, hits, puts, stale] (8B BE each)
+//! Commands without the NS prefix use the `default` namespace.
+//! Introspection:
+//!   INFO:      ["INFO"]  -> ["OK", name, value, name, value, ...] (UTF-8 text;
+//!              lists are space-separated, see `kvz::metrics::info` for the fields)
+//!   SLOWLOG:   ["SLOWLOG", count(8B BE)?] -> ["OK", entry...], newest first, each
+//!              entry 10 frames (see `kvz::slowlog::SlowEntry::frames`)
+//!   SLOWLOGRESET: ["SLOWLOGRESET"] -> ["OK", removed_entries(8B BE)]
+//!   HOTKEYS:   ["HOTKEYS", "reads"|"writes"|"largest"] -> ["OK", (namespace, key, count(8B BE))...],
+//!              highest first; counts are estimates (value sizes for "largest")
+
+use crate::acl::{Access, Acl};
+use crate::auth::{frame_user_id, ANONYMOUS};
+use crate::hotkeys::{HotKeys, HotKind};
+use crate::limits::Limits;
+use crate::logging::{next_request_id, spans_enabled, SPAN_TARGET};
+use crate::metrics::{command_index, info, reply_kind, Metrics, COMMANDS, REPLY_KINDS};
+use crate::monitor::Monitor;
+use crate::namespace::{Namespace, Namespaces, DEFAULT_NAMESPACE};
+use crate::payload::{send_payload, Payload};
+use crate::protocol::{read_u64, send_err, ErrorCode, KvzError};
+use crate::quota::{PrefixQuotas, RateLimiter};
+use crate::session::Sessions;
+use crate::slowlog::SlowLog;
+use crate::store::{take_lock_wait, Put, Value};
+use anyhow::Result;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{field, trace_span, Span};
+
+/// Upper bound on frames per request; no command uses more than a handful.
+pub const MAX_FRAMES: usize = 16;
+
+/// Longest key prefix kept in a [`Summary`].
+pub const MAX_SUMMARY_KEY: usize = 128;
+
+pub enum Reply {
+    Ok,
+    Stale,
+    Miss,
+    Value { ts: u64, data: Arc<Payload> },
+    Limits(Limits),
+    /// `["OK", frames...]`
+    Frames(Vec<Vec<u8>>),
+    Err(KvzError),
+}
+
+impl Reply {
+    pub fn err(code: ErrorCode, msg: impl Into<String>) -> Reply {
+        Reply::Err(KvzError::new(code, msg))
+    }
+
+    pub fn send(&self, sock: &mut zmq::Socket) -> Result<()> {
+        match self {
+            Reply::Ok => sock.send_multipart([b"OK".as_slice()], 0)?,
+            Reply::Stale => sock.send_multipart([b"STALE".as_slice()], 0)?,
+            Reply::Miss => sock.send_multipart([b"MISS".as_slice()], 0)?,
+            Reply::Value { ts, data } => {
+                sock.send("OK", zmq::SNDMORE)?;
+                sock.send(&ts.to_be_bytes()[..], zmq::SNDMORE)?;
+                send_payload(sock, data, 0)?
+            }
+            Reply::Limits(l) => {
+                let key = (l.max_key_bytes as u64).to_be_bytes();
+                let value = (l.max_value_bytes as u64).to_be_bytes();
+                let msg = (l.max_msg_bytes() as u64).to_be_bytes();
+                sock.send_multipart([b"OK".as_slice(), &key, &value, &msg], 0)?
+            }
+            // send_multipart sends nothing for no frames, which would leave
+            // the OK unterminated and the REP socket stuck.
+            Reply::Frames(frames) if frames.is_empty() => sock.send("OK", 0)?,
+            Reply::Frames(frames) => {
+                sock.send("OK", zmq::SNDMORE)?;
+                sock.send_multipart(frames, 0)?
+            }
+            Reply::Err(e) => send_err(sock, e)?,
+        }
+        Ok(())
+    }
+
+    /// Payload bytes carried by the reply (values and extra frames).
+    pub fn payload_len(&self) -> usize {
+        match self {
+            Reply::Value { data, .. } => data.len(),
+            Reply::Frames(frames) => frames.iter().map(Vec::len).sum(),
+            _ => 0,
+        }
+    }
+}
+
+impl From<Result<Reply, KvzError>> for Reply {
+    fn from(r: Result<Reply, KvzError>) -> Reply {
+        r.unwrap_or_else(Reply::Err)
+    }
+}
+
+/// A received request and the client identity it arrived under.
+pub struct Request {
+    /// ZAP user id, or [`ANONYMOUS`] without authentication.
+    pub identity: String,
+    /// Rate limiting key: the identity if authenticated, else the connection.
+    pub client: String,
+    pub frames: Vec<zmq::Message>,
+}
+
+/// What the slow log and the monitor record about a request.
+pub struct Summary {
+    pub command: usize,
+    /// Empty when the request has no `NS` prefix.
+    pub namespace: String,
+    /// The key (at most [`MAX_SUMMARY_KEY`] bytes), empty if there is none.
+    pub key: Vec<u8>,
+    /// Size of the value carried by the request.
+    pub value_size: usize,
+    /// Up to the first `value_bytes` bytes of that value.
+    pub value: Vec<u8>,
+    pub client: String,
+}
+
+impl Summary {
+    /// The namespace frame of `frames`, if any, and the frames from the
+    /// command on.
+    fn split(frames: &[zmq::Message]) -> (Option<&zmq::Message>, &[zmq::Message]) {
+        match frames.first() {
+            Some(f) if &f[..] == b"NS" && frames.len() > 2 => (Some(&frames[1]), &frames[2..]),
+            _ => (None, frames),
+        }
+    }
+
+    /// Whether the command's second frame is a key, and where its value
+    /// frame is.
+    fn layout(command: usize) -> (bool, Option<usize>) {
+        match COMMANDS[command] {
+            "PUT" => (true, Some(3)),
+            "GET" | "GETRANGE" | "PUTBEGIN" | "GETBEGIN" => (true, None),
+            "PUTCHUNK" => (false, Some(2)),
+            _ => (false, None),
+        }
+    }
+
+    /// Size of the value a request carries. Take it before handling: PUT
+    /// moves its value frame into the store.
+    pub fn value_size(frames: &[zmq::Message]) -> usize {
+        let (_, args) = Summary::split(frames);
+        Summary::layout(command_index(frames)).1.and_then(|i| args.get(i)).map_or(0, |f| f.len())
+    }
+
+    /// Summarise `req`, with `value_size` from [`Summary::value_size`] and
+    /// up to `value_bytes` of the value, which is only still there before
+    /// the request is handled.
+    pub fn of(req: &Request, value_size: usize, value_bytes: usize) -> Summary {
+        let (namespace, args) = Summary::split(&req.frames);
+        let command = command_index(&req.frames);
+        let (key, value) = Summary::layout(command);
+        let key = match args.get(1) {
+            Some(k) if key => k[..k.len().min(MAX_SUMMARY_KEY)].to_vec(),
+            _ => Vec::new(),
+        };
+        let value = value.and_then(|i| args.get(i)).map_or(&[][..], |f| &f[..]);
+        Summary {
+            command,
+            namespace: namespace.map(|ns| String::from_utf8_lossy(ns).into_owned()).unwrap_or_default(),
+            key,
+            value_size,
+            value: value[..value.len().min(value_bytes)].to_vec(),
+            client: req.client.clone(),
+        }
+    }
+}
+
+/// Receive one request frame by frame, refusing to buffer more than the
+/// configured limits. Oversized requests are drained and reported as errors.
+pub fn recv_request(sock: &zmq::Socket, limits: &Limits) -> Result<Result<Request, KvzError>> {
+    recv_frames(sock, limits, None)
+}
+
+/// Like [`recv_request`], for workers behind kvz-router's proxy, which puts
+/// the identity and client in frames of their own ahead of the request.
+pub fn recv_tagged_request(sock: &zmq::Socket, limits: &Limits) -> Result<Result<Request, KvzError>> {
+    let mut tags = [String::new(), String::new()];
+    for tag in &mut tags {
+        let frame = sock.recv_msg(0)?;
+        *tag = String::from_utf8_lossy(&frame).into_owned();
+        if !frame.get_more() {
+            return Ok(Err(KvzError::new(ErrorCode::EmptyMessage, "empty message")));
+        }
+    }
+    let [identity, client] = tags;
+    recv_frames(sock, limits, Some((identity, client)))
+}
+
+/// `(identity, client)` for a request received directly from its client.
+fn peer_tags(frame: &mut zmq::Message) -> (String, String) {
+    let user = frame_user_id(frame);
+    let client = match &user {
+        Some(id) => id.clone(),
+        None => match frame.gets("Peer-Address") {
+            Some(addr) => format!("addr:{addr}"),
+            None => ANONYMOUS.to_string(),
+        },
+    };
+    (user.unwrap_or_else(|| ANONYMOUS.to_string()), client)
+}
+
+fn recv_frames(
+    sock: &zmq::Socket,
+    limits: &Limits,
+    mut tags: Option<(String, String)>,
+) -> Result<Result<Request, KvzError>> {
+    let max_total = limits.max_msg_bytes();
+    let mut msg = Vec::new();
+    let mut total = 0usize;
+    let mut err = None;
+    loop {
+        let mut frame = sock.recv_msg(0)?;
+        if tags.is_none() {
+            tags = Some(peer_tags(&mut frame));
+        }
+        let more = frame.get_more();
+        total = total.saturating_add(frame.len());
+        if err.is_none() {
+            if msg.len() == MAX_FRAMES {
+                err = Some(KvzError::new(
+                    ErrorCode::BadFrameCount,
+                    format!("request has more than {MAX_FRAMES} frames"),
+                ));
+            } else if total > max_total {
+                err = Some(KvzError::new(
+                    ErrorCode::MessageTooLarge,
+                    format!("request exceeds {max_total} bytes"),
+                ));
+            }
+        }
+        if err.is_none() {
+            msg.push(frame);
+        } else {
+            msg.clear();
+        }
+        if !more {
+            break;
+        }
+    }
+    Ok(match err {
+        Some(e) => Err(e),
+        None => {
+            let (identity, client) = tags.expect("at least one frame was received");
+            Ok(Request { identity, client, frames: msg })
+        }
+    })
+}
+
+fn expect_frames(msg: &[zmq::Message], n: usize, cmd: &str) -> Result<(), KvzError> {
+    if msg.len() != n {
+        let s = if n == 1 { "" } else { "s" };
+        return Err(KvzError::new(ErrorCode::BadFrameCount, format!("{cmd} expects {n} frame{s}")));
+    }
+    Ok(())
+}
+
+fn frame_u64(frame: &[u8], what: &str) -> Result<u64, KvzError> {
+    read_u64(frame)
+        .ok_or_else(|| KvzError::new(ErrorCode::BadArgument, format!("{what} must be 8 bytes (u64 BE)")))
+}
+
+fn frame_name(frame: &[u8]) -> Result<String, KvzError> {
+    std::str::from_utf8(frame)
+        .map(str::to_string)
+        .map_err(|_| KvzError::new(ErrorCode::BadArgument, "namespace must be UTF-8"))
+}
+
+fn store_err(e: anyhow::Error) -> KvzError {
+    KvzError::new(ErrorCode::StoreError, format!("store error: {e}"))
+}
+
+/// Who a request is from and which namespace it addresses.
+struct Ctx<'a> {
+    id: &'a str,
+    /// Rate-limit key: `id` if authenticated, else the peer address.
+    client: &'a str,
+    ns: &'a str,
+}
+
+fn bump(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Server state shared by all workers.
+pub struct Server {
+    pub namespaces: Namespaces,
+    pub limits: Limits,
+    /// Key-prefix permissions; None allows everything.
+    pub acl: Option<Acl>,
+    /// Per-client token buckets; None means unlimited.
+    pub rate_limit: Option<RateLimiter>,
+    pub prefix_quotas: PrefixQuotas,
+    pub metrics: Metrics,
+    pub slowlog: SlowLog,
+    pub hotkeys: HotKeys,
+    /// Live request stream; None unless `--monitor-bind` is given.
+    pub monitor: Option<Monitor>,
+    sessions: Sessions,
+}
+
+impl Server {
+    pub fn new(shards: usize, limits: Limits) -> Self {
+        Server {
+            namespaces: Namespaces::new(shards),
+            limits,
+            acl: None,
+            rate_limit: None,
+            prefix_quotas: PrefixQuotas::default(),
+            metrics: Metrics::default(),
+            slowlog: SlowLog::default(),
+            hotkeys: HotKeys::default(),
+            monitor: None,
+            sessions: Sessions::new(limits.max_upload_bytes),
+        }
+    }
+
+    /// Worker loop: receive with `recv`, handle, reply, and record metrics.
+    /// Returns only on socket errors.
+    pub fn serve<R>(&self, sock: &mut zmq::Socket, worker: usize, recv: R) -> Result<()>
+    where
+        R: Fn(&zmq::Socket, &Limits) -> Result<Result<Request, KvzError>>,
+    {
+        let stats = self.metrics.worker(worker);
+        loop {
+            let span = if spans_enabled() {
+                // Wait for input outside the request span, so it times
+                // parsing rather than idling.
+                sock.poll(zmq::POLLIN, -1)?;
+                trace_span!(
+                    target: SPAN_TARGET,
+                    "request",
+                    req = next_request_id(),
+                    worker,
+                    command = field::Empty,
+                    reply = field::Empty,
+                    lock_wait_us = field::Empty,
+                )
+            } else {
+                Span::none()
+            };
+            let _request = span.enter();
+            let mut req = trace_span!(target: SPAN_TARGET, "parse").in_scope(|| recv(sock, &self.limits))?;
+            let start = Instant::now();
+            take_lock_wait();
+            let monitor = self.monitor.as_ref().filter(|m| m.wants());
+            let (command, value_size) = match &req {
+                Ok(req) => (command_index(&req.frames), Summary::value_size(&req.frames)),
+                Err(_) => (COMMANDS.len() - 1, 0),
+            };
+            // The monitor streams every request, so it summarises them up
+            // front, while the start of a PUT's value is still in the frames.
+            let mut summary = match (&req, monitor) {
+                (Ok(req), Some(m)) => Some(Summary::of(req, value_size, m.value_bytes())),
+                _ => None,
+            };
+            let reply = match &mut req {
+                Ok(req) => trace_span!(target: SPAN_TARGET, "handle").in_scope(|| self.handle(req)),
+                Err(e) => Reply::Err(e.clone()),
+            };
+            trace_span!(target: SPAN_TARGET, "reply").in_scope(|| reply.send(sock))?;
+            let elapsed = start.elapsed();
+            // Shard lock waits are recorded here rather than in spans of
+            // their own, so nothing is logged while a shard lock is held.
+            let lock_wait = take_lock_wait();
+            if !span.is_disabled() {
+                span.record("command", COMMANDS[command]);
+                span.record("reply", REPLY_KINDS[reply_kind(&reply)]);
+                span.record("lock_wait_us", lock_wait.as_nanos() as f64 / 1e3);
+            }
+            self.metrics.observe(command, reply_kind(&reply), elapsed);
+            stats.add_busy(elapsed);
+            if let (Some(m), Some(summary)) = (monitor, &summary) {
+                m.publish(summary, &reply, worker, elapsed);
+            }
+            // Only slow requests need a summary for the slow log.
+            if self.slowlog.is_slow(elapsed)
+                && let Ok(req) = &req
+            {
+                let summary = summary.take().unwrap_or_else(|| Summary::of(req, value_size, 0));
+                self.slowlog.record(summary, elapsed, lock_wait, worker, reply.payload_len());
+            }
+        }
+    }
+
+    /// Execute one request against the store. The frames are left in place
+    /// for [`Summary::of`], except the value of a PUT, which moves into the
+    /// store.
+    pub fn handle(&self, req: &mut Request) -> Reply {
+        let Request { identity, client, frames } = req;
+        if frames.is_empty() {
+            return Reply::err(ErrorCode::EmptyMessage, "empty message");
+        }
+        if let Some(rl) = &self.rate_limit
+            && let Err(e) = rl.admit(client, frames.iter().map(|f| f.len()).sum())
+        {
+            return Reply::Err(e);
+        }
+        // ["NS", name, command...] addresses a namespace other than the default.
+        let (ns_frame, msg) = if &frames[0][..] == b"NS" {
+            if frames.len() < 3 {
+                return Reply::err(ErrorCode::BadFrameCount, "NS expects a namespace and a command");
+            }
+            let (ns, msg) = frames.split_at_mut(2);
+            (Some(&ns[1]), msg)
+        } else {
+            (None, &mut frames[..])
+        };
+        let ns = match ns_frame.map(|f| std::str::from_utf8(f)) {
+            None => DEFAULT_NAMESPACE,
+            Some(Ok(name)) => name,
+            Some(Err(_)) => return Reply::err(ErrorCode::BadArgument, "namespace must be UTF-8"),
+        };
+        let reply: Reply = self.dispatch(&Ctx { id: identity, client, ns }, msg).into();
+        if let Some(rl) = &self.rate_limit {
+            rl.charge(client, reply.payload_len());
+        }
+        reply
+    }
+
+    fn dispatch(&self, cx: &Ctx, msg: &mut [zmq::Message]) -> Result<Reply, KvzError> {
+        let cmd = std::str::from_utf8(&msg[0]).unwrap_or("");
+        match cmd {
+            "PUT" => self.put(cx, msg),
+            "GET" => self.get(cx, msg),
+            "GETRANGE" => self.get_range(cx, msg),
+            "LIMITS" => expect_frames(msg, 1, "LIMITS").map(|_| Reply::Limits(self.limits)),
+            "PUTBEGIN" => self.put_begin(cx, msg),
+            "PUTCHUNK" => self.put_chunk(cx, msg),
+            "PUTCOMMIT" => self.put_commit(cx, msg),
+            "PUTABORT" => self.put_abort(cx, msg),
+            "GETBEGIN" => self.get_begin(cx, msg),
+            "GETCHUNK" => self.get_chunk(cx, msg),
+            "GETEND" => self.get_end(cx, msg),
+            "NSCREATE" => self.ns_create(cx, msg),
+            "NSDROP" => self.ns_drop(cx, msg),
+            "NSFLUSH" => self.ns_flush(cx, msg),
+            "NSLIST" => self.ns_list(cx, msg),
+            "NSSTATS" => self.ns_stats(cx, msg),
+            "INFO" => self.info(cx, msg),
+            "SLOWLOG" => self.slowlog_get(cx, msg),
+            "SLOWLOGRESET" => self.slowlog_reset(cx, msg),
+            "HOTKEYS" => self.hot_keys(cx, msg),
+            _ => Err(KvzError::new(ErrorCode::UnknownCommand, "unknown command")),
+        }
+    }
+
+    /// Enforce `--acl`, if configured, for `key` in namespace `ns`; None for
+    /// commands that span every namespace.
+    fn authorize(&self, id: &str, access: Access, ns: Option<&str>, key: &[u8]) -> Result<(), KvzError> {
+        match &self.acl {
+            Some(acl) => acl.check(id, access, ns, key),
+            None => Ok(()),
+        }
+    }
+
+    /// Store into `ns`, enforcing namespace and prefix quotas and counting the write.
+    fn store_put(&self, ns: &Namespace, key: Vec<u8>, ts: u64, data: Payload) -> Result<Reply, KvzError> {
+        bump(&ns.stats.puts);
+        self.hotkeys.write(&ns.name, &key);
+        let quotas = &self.prefix_quotas;
+        let mut refused = None;
+        let admit = |key: &[u8], growth: usize| {
+            if let Some(q) = ns.quota()
+                && ns.store.bytes() + growth > q
+            {
+                refused = Some(format!("namespace quota of {q} bytes exceeded"));
+            } else if let Some(q) = quotas.exceeded(key, growth) {
+                let prefix = String::from_utf8_lossy(&q.prefix);
+                refused = Some(format!("quota of {} bytes for prefix {prefix:?} exceeded", q.limit));
+            }
+            refused.is_none()
+        };
+        // The key moves into the store, so keep copies for prefix accounting
+        // and the largest-values list where needed.
+        let prefix_key = (!quotas.is_empty()).then(|| key.clone());
+        let size = data.len();
+        let size_key = self.hotkeys.wants_size(&ns.name, &key, size).then(|| key.clone());
+        match ns.store.put(key, ts, data, admit).map_err(store_err)? {
+            Put::Stored { delta } => {
+                if let Some(k) = prefix_key {
+                    quotas.account(&k, delta);
+                }
+                if let Some(k) = size_key {
+                    self.hotkeys.stored(&ns.name, &k, size);
+                }
+                Ok(Reply::Ok)
+            }
+            Put::Stale => {
+                bump(&ns.stats.stale);
+                Ok(Reply::Stale)
+            }
+            Put::OverQuota => Err(KvzError::new(ErrorCode::QuotaExceeded, refused.unwrap_or_default())),
+            Put::Closed => Err(KvzError::new(ErrorCode::NoNamespace, format!("namespace {:?} was dropped", ns.name))),
+        }
+    }
+
+    /// Look up a key in `ns`, counting the read.
+    fn store_get(&self, ns: &Namespace, key: &[u8]) -> Result<Option<Value>, KvzError> {
+        bump(&ns.stats.gets);
+        self.hotkeys.read(&ns.name, key);
+        let v = ns.store.get(key).map_err(store_err)?;
+        if v.is_some() {
+            bump(&ns.stats.hits);
+        }
+        Ok(v)
+    }
+
+    fn put(&self, cx: &Ctx, msg: &mut [zmq::Message]) -> Result<Reply, KvzError> {
+        // Expect 4 frames: "PUT", key, ts(8), data
+        expect_frames(msg, 4, "PUT")?;
+        self.limits.check_key(&msg[1])?;
+        self.authorize(cx.id, Access::Write, Some(cx.ns), &msg[1])?;
+        self.limits.check_value(msg[3].len())?;
+        let Some(ts) = read_u64(&msg[2]) else {
+            return Err(KvzError::new(ErrorCode::BadTimestamp, "timestamp must be 8 bytes (u64 BE)"));
+        };
+        let ns = self.namespaces.get(cx.ns)?;
+        // Large frames are retained as-is rather than copied.
+        let data = Payload::from_frame(std::mem::replace(&mut msg[3], zmq::Message::new()));
+        let key = msg[1].to_vec();
+        self.store_put(&ns, key, ts, data)
+    }
+
+    fn get(&self, cx: &Ctx, msg: &[zmq::Message]) -> Result<Reply, KvzError> {
+        // Expect 2 frames: "GET", key
+        expect_frames(msg, 2, "GET")?;
+        self.limits.check_key(&msg[1])?;
+        self.authorize(cx.id, Access::Read, Some(cx.ns), &msg[1])?;
+        let ns = self.namespaces.get(cx.ns)?;
+        match self.store_get(&ns, &msg[1])? {
+            Some(v) => Ok(Reply::Value { ts: v.ts, data: v.data }),
+            None => Ok(Reply::Miss),
+        }
+    }
+
+    fn get_range(&self, cx: &Ctx, msg: &[zmq::Message]) -> Result<Reply, KvzError> {
+        expect_frames(msg, 4, "GETRANGE")?;
+        self.limits.check_key(&msg[1])?;
+        self.authorize(cx.id, Access::Read, Some(cx.ns), &msg[1])?;
+        let offset = frame_u64(&msg[2], "offset")?;
+        let len = frame_u64(&msg[3], "length")?;
+        let ns = self.namespaces.get(cx.ns)?;
+        let Some(v) = self.store_get(&ns, &msg[1])? else {
+            return Ok(Reply::Miss);
+        };
+        // Only the requested slice is copied; the value itself is shared.
+        let total = v.data.len();
+        let start = usize::try_from(offset).unwrap_or(usize::MAX).min(total);
+        let end = start + usize::try_from(len).unwrap_or(usize::MAX).min(total - start);
+        Ok(Reply::Frames(vec![
+            v.ts.to_be_bytes().to_vec(),
+            (total as u64).to_be_bytes().to_vec(),
+            v.data[start..end].to_vec(),
+        ]))
+    }
+
+    fn put_begin(&self, cx: &Ctx, msg: &[zmq::Message]) -> Result<Reply, KvzError> {
+        expect_frames(msg, 3, "PUTBEGIN")?;
+        self.limits.check_key(&msg[1])?;
+        self.authorize(cx.id, Access::Write, Some(cx.ns), &msg[1])?;
+        let Some(ts) = read_u64(&msg[2]) else {
+            return Err(KvzError::new(ErrorCode::BadTimestamp, "timestamp must be 8 bytes (u64 BE)"));
+        };
+        // Fail early rather than after the whole value has been uploaded.
+        self.namespaces.get(cx.ns)?;
+        let sid = self.sessions.begin_upload(cx.client, cx.ns, msg[1].to_vec(), ts)?;
+        Ok(Reply::Frames(vec![sid.to_be_bytes().to_vec()]))
+    }
+
+    fn put_chunk(&self, cx: &Ctx, msg: &[zmq::Message]) -> Result<Reply, KvzError> {
+        expect_frames(msg, 3, "PUTCHUNK")?;
+        let sid = frame_u64(&msg[1], "session id")?;
+        self.sessions.append(cx.client, sid, &msg[2], self.limits.max_value_bytes)?;
+        Ok(Reply::Ok)
+    }
+
+    fn put_commit(&self, cx: &Ctx, msg: &[zmq::Message]) -> Result<Reply, KvzError> {
+        expect_frames(msg, 2, "PUTCOMMIT")?;
+        let sid = frame_u64(&msg[1], "session id")?;
+        let up = self.sessions.finish_upload(cx.client, sid)?;
+        let ns = self.namespaces.get(&up.ns)?;
+        self.store_put(&ns, up.key, up.ts, up.data.into())
+    }
+
+    fn put_abort(&self, cx: &Ctx, msg: &[zmq::Message]) -> Result<Reply, KvzError> {
+        expect_frames(msg, 2, "PUTABORT")?;
+        let sid = frame_u64(&msg[1], "session id")?;
+        self.sessions.finish_upload(cx.client, sid)?;
+        Ok(Reply::Ok)
+    }
+
+    fn get_begin(&self, cx: &Ctx, msg: &[zmq::Message]) -> Result<Reply, KvzError> {
+        expect_frames(msg, 3, "GETBEGIN")?;
+        self.limits.check_key(&msg[1])?;
+        self.authorize(cx.id, Access::Read, Some(cx.ns), &msg[1])?;
+        let chunk_len = self.chunk_len(&msg[2])?;
+        let ns = self.namespaces.get(cx.ns)?;
+        let Some(v) = self.store_get(&ns, &msg[1])? else {
+            return Ok(Reply::Miss);
+        };
+        let (ts, total) = (v.ts, v.data.len());
+        let (sid, first) = if total <= chunk_len {
+            (0, v.data.to_vec())
+        } else {
+            let sid = self.sessions.begin_download(cx.client, v)?;
+            (sid, self.sessions.next_chunk(cx.client, sid, chunk_len)?)
+        };
+        Ok(Reply::Frames(vec![
+            sid.to_be_bytes().to_vec(),
+            ts.to_be_bytes().to_vec(),
+            (total as u64).to_be_bytes().to_vec(),
+            first,
+        ]))
+    }
+
+    /// A download chunk length: at least 1, and no larger than any value can be.
+    fn chunk_len(&self, frame: &[u8]) -> Result<usize, KvzError> {
+        match frame_u64(frame, "chunk length")? {
+            0 => Err(KvzError::new(ErrorCode::BadArgument, "chunk length must be at least 1")),
+            n => Ok(usize::try_from(n).unwrap_or(usize::MAX).min(self.limits.max_value_bytes.max(1))),
+        }
+    }
+
+    fn get_chunk(&self, cx: &Ctx, msg: &[zmq::Message]) -> Result<Reply, KvzError> {
+        expect_frames(msg, 3, "GETCHUNK")?;
+        let sid = frame_u64(&msg[1], "session id")?;
+        let chunk_len = self.chunk_len(&msg[2])?;
+        let chunk = self.sessions.next_chunk(cx.client, sid, chunk_len)?;
+        Ok(Reply::Frames(vec![chunk]))
+    }
+
+    fn get_end(&self, cx: &Ctx, msg: &[zmq::Message]) -> Result<Reply, KvzError> {
+        expect_frames(msg, 2, "GETEND")?;
+        let sid = frame_u64(&msg[1], "session id")?;
+        self.sessions.end_download(cx.client, sid)?;
+        Ok(Reply::Ok)
+    }
+
+    // Namespace administration, INFO, SLOWLOG and HOTKEYS need an ACL rule covering all keys in all namespaces.
+
+    fn ns_create(&self, cx: &Ctx, msg: &[zmq::Message]) -> Result<Reply, KvzError> {
+        expect_frames(msg, 3, "NSCREATE")?;
+        self.authorize(cx.id, Access::Write, None, b"")?;
+        let quota = frame_u64(&msg[2], "quota")?;
+        self.namespaces.create(&frame_name(&msg[1])?, quota)?;
+        Ok(Reply::Ok)
+    }
+
+    fn ns_drop(&self, cx: &Ctx, msg: &[zmq::Message]) -> Result<Reply, KvzError> {
+        expect_frames(msg, 2, "NSDROP")?;
+        self.authorize(cx.id, Access::Write, None, b"")?;
+        let ns = self.namespaces.drop(&frame_name(&msg[1])?)?;
+        self.clear(&ns)?;
+        Ok(Reply::Ok)
+    }
+
+    fn ns_flush(&self, cx: &Ctx, msg: &[zmq::Message]) -> Result<Reply, KvzError> {
+        expect_frames(msg, 2, "NSFLUSH")?;
+        self.authorize(cx.id, Access::Write, None, b"")?;
+        let ns = self.namespaces.get(&frame_name(&msg[1])?)?;
+        let removed = self.clear(&ns)?;
+        Ok(Reply::Frames(vec![(removed as u64).to_be_bytes().to_vec()]))
+    }
+
+    /// Empty a namespace, releasing its share of the prefix quotas.
+    fn clear(&self, ns: &Namespace) -> Result<usize, KvzError> {
+        self.hotkeys.forget(&ns.name);
+        ns.store
+            .clear(|key, size| self.prefix_quotas.account(key, -(size as isize)))
+            .map_err(store_err)
+    }
+
+    fn ns_list(&self, cx: &Ctx, msg: &[zmq::Message]) -> Result<Reply, KvzError> {
+        expect_frames(msg, 1, "NSLIST")?;
+        self.authorize(cx.id, Access::Read, None, b"")?;
+        let names = self.namespaces.list()?.into_iter().map(|(name, _)| name.into_bytes()).collect();
+        Ok(Reply::Frames(names))
+    }
+
+    fn ns_stats(&self, cx: &Ctx, msg: &[zmq::Message]) -> Result<Reply, KvzError> {
+        expect_frames(msg, 2, "NSSTATS")?;
+        self.authorize(cx.id, Access::Read, None, b"")?;
+        let ns = self.namespaces.get(&frame_name(&msg[1])?)?;
+        let load = |c: &AtomicU64| c.load(Ordering::Relaxed);
+        let fields = [
+            ns.store.len() as u64,
+            ns.store.bytes() as u64,
+            ns.quota().unwrap_or(0) as u64,
+            load(&ns.stats.gets),
+            load(&ns.stats.hits),
+            load(&ns.stats.puts),
+            load(&ns.stats.stale),
+        ];
+        Ok(Reply::Frames(fields.iter().map(|v| v.to_be_bytes().to_vec()).collect()))
+    }
+
+    fn info(&self, cx: &Ctx, msg: &[zmq::Message]) -> Result<Reply, KvzError> {
+        expect_frames(msg, 1, "INFO")?;
+        self.authorize(cx.id, Access::Read, None, b"")?;
+        let frames = info(self).into_iter().flat_map(|(k, v)| [k.into_bytes(), v.into_bytes()]).collect();
+        Ok(Reply::Frames(frames))
+    }
+
+    fn slowlog_get(&self, cx: &Ctx, msg: &[zmq::Message]) -> Result<Reply, KvzError> {
+        if msg.len() > 2 {
+            return Err(KvzError::new(ErrorCode::BadFrameCount, "SLOWLOG expects 1 or 2 frames"));
+        }
+        self.authorize(cx.id, Access::Read, None, b"")?;
+        let count = match msg.get(1) {
+            Some(f) => usize::try_from(frame_u64(f, "count")?).unwrap_or(usize::MAX),
+            None => usize::MAX,
+        };
+        let frames = self.slowlog.latest(count).iter().flat_map(|e| e.frames()).collect();
+        Ok(Reply::Frames(frames))
+    }
+
+    fn hot_keys(&self, cx: &Ctx, msg: &[zmq::Message]) -> Result<Reply, KvzError> {
+        expect_frames(msg, 2, "HOTKEYS")?;
+        self.authorize(cx.id, Access::Read, None, b"")?;
+        let kind = std::str::from_utf8(&msg[1]).ok().and_then(HotKind::parse).ok_or_else(|| {
+            KvzError::new(ErrorCode::BadArgument, "HOTKEYS expects reads, writes or largest")
+        })?;
+        let frames = self
+            .hotkeys
+            .top(kind)
+            .into_iter()
+            .flat_map(|h| [h.namespace.into_bytes(), h.key, h.count.to_be_bytes().to_vec()])
+            .collect();
+        Ok(Reply::Frames(frames))
+    }
+
+    fn slowlog_reset(&self, cx: &Ctx, msg: &[zmq::Message]) -> Result<Reply, KvzError> {
+        expect_frames(msg, 1, "SLOWLOGRESET")?;
+        self.authorize(cx.id, Access::Write, None, b"")?;
+        let removed = self.slowlog.reset() as u64;
+        Ok(Reply::Frames(vec![removed.to_be_bytes().to_vec()]))
+    }
+}
@@ -0,0 +1,207 @@
+//! Chunked upload/download sessions for values too large for one message.
+//!
+//! Uploads buffer chunks server-side and apply key + timestamp atomically at
+//! commit. Downloads hold a snapshot of the value, so a concurrent overwrite
+//! never mixes bytes from two versions.
+//!
+//! A session belongs to the client that opened it (its rate-limit key: the
+//! authenticated identity, else the peer address); anyone else is told the
+//! session does not exist.
+
+use crate::protocol::{ErrorCode, KvzError};
+use crate::store::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Sessions idle for longer than this are dropped.
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Upper bound on concurrently open sessions (uploads + downloads).
+const MAX_SESSIONS: usize = 1024;
+
+pub struct Upload {
+    owner: String,
+    /// Namespace the value is committed to.
+    pub ns: String,
+    pub key: Vec<u8>,
+    pub ts: u64,
+    pub data: Vec<u8>,
+    touched: Instant,
+}
+
+struct Download {
+    owner: String,
+    value: Value,
+    offset: usize,
+    touched: Instant,
+}
+
+#[derive(Default)]
+struct SessionMap {
+    uploads: HashMap<u64, Upload>,
+    downloads: HashMap<u64, Download>,
+    /// Bytes held by all uploads.
+    buffered: usize,
+}
+
+impl SessionMap {
+    fn expire(&mut self, now: Instant) {
+        let buffered = &mut self.buffered;
+        self.uploads.retain(|_, u| {
+            let keep = now.duration_since(u.touched) < SESSION_IDLE_TIMEOUT;
+            if !keep {
+                *buffered -= u.data.len();
+            }
+            keep
+        });
+        self.downloads
+            .retain(|_, d| now.duration_since(d.touched) < SESSION_IDLE_TIMEOUT);
+    }
+
+    fn len(&self) -> usize {
+        self.uploads.len() + self.downloads.len()
+    }
+
+    fn upload(&mut self, sid: u64, client: &str) -> Result<&mut Upload, KvzError> {
+        self.uploads.get_mut(&sid).filter(|u| u.owner == client).ok_or_else(|| no_session(sid))
+    }
+
+    fn download(&mut self, sid: u64, client: &str) -> Result<&mut Download, KvzError> {
+        self.downloads.get_mut(&sid).filter(|d| d.owner == client).ok_or_else(|| no_session(sid))
+    }
+
+    fn remove_upload(&mut self, sid: u64) -> Option<Upload> {
+        let up = self.uploads.remove(&sid)?;
+        self.buffered -= up.data.len();
+        Some(up)
+    }
+
+    fn new_id(&self) -> u64 {
+        loop {
+            let id = rand::random::<u64>();
+            if !self.uploads.contains_key(&id) && !self.downloads.contains_key(&id) {
+                return id;
+            }
+        }
+    }
+}
+
+pub struct Sessions {
+    inner: Mutex<SessionMap>,
+    /// Cap on [`SessionMap::buffered`].
+    max_buffered: usize,
+}
+
+fn no_session(sid: u64) -> KvzError {
+    KvzError::new(ErrorCode::NoSession, format!("unknown or expired session {sid:016x}"))
+}
+
+impl Sessions {
+    pub fn new(max_buffered: usize) -> Sessions {
+        Sessions { inner: Mutex::default(), max_buffered }
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, SessionMap>, KvzError> {
+        self.inner
+            .lock()
+            .map_err(|_| KvzError::new(ErrorCode::StoreError, "session table poisoned"))
+    }
+
+    /// Reserve a session slot, expiring idle sessions first.
+    fn open(&self) -> Result<(std::sync::MutexGuard<'_, SessionMap>, u64), KvzError> {
+        let mut m = self.lock()?;
+        m.expire(Instant::now());
+        if m.len() >= MAX_SESSIONS {
+            return Err(KvzError::new(
+                ErrorCode::OverLimit,
+                format!("too many open sessions (limit {MAX_SESSIONS})"),
+            ));
+        }
+        let sid = m.new_id();
+        Ok((m, sid))
+    }
+
+    pub fn begin_upload(&self, client: &str, ns: &str, key: Vec<u8>, ts: u64) -> Result<u64, KvzError> {
+        let (mut m, sid) = self.open()?;
+        let up = Upload {
+            owner: client.to_string(),
+            ns: ns.to_string(),
+            key,
+            ts,
+            data: Vec::new(),
+            touched: Instant::now(),
+        };
+        m.uploads.insert(sid, up);
+        Ok(sid)
+    }
+
+    /// Append a chunk; `max_len` bounds the assembled value. The copy happens
+    /// under the lock so the session stays visible to concurrent requests and
+    /// the buffered total stays exact.
+    pub fn append(&self, client: &str, sid: u64, chunk: &[u8], max_len: usize) -> Result<(), KvzError> {
+        let mut m = self.lock()?;
+        let held = m.upload(sid, client)?.data.len();
+        let len = held + chunk.len();
+        if len > max_len {
+            // Drop the session: a partial value can never be committed.
+            m.remove_upload(sid);
+            return Err(KvzError::new(
+                ErrorCode::ValueTooLarge,
+                format!("value is at least {len} bytes, limit is {max_len}"),
+            ));
+        }
+        if m.buffered + chunk.len() > self.max_buffered {
+            // Keep the session; the client may retry once others commit.
+            return Err(KvzError::new(
+                ErrorCode::OverLimit,
+                format!("upload buffers full ({} of {} bytes in use)", m.buffered, self.max_buffered),
+            ));
+        }
+        m.buffered += chunk.len();
+        let up = m.upload(sid, client)?;
+        up.data.extend_from_slice(chunk);
+        up.touched = Instant::now();
+        Ok(())
+    }
+
+    /// Remove an upload for commit or abort.
+    pub fn finish_upload(&self, client: &str, sid: u64) -> Result<Upload, KvzError> {
+        let mut m = self.lock()?;
+        m.upload(sid, client)?;
+        m.remove_upload(sid).ok_or_else(|| no_session(sid))
+    }
+
+    pub fn begin_download(&self, client: &str, value: Value) -> Result<u64, KvzError> {
+        let (mut m, sid) = self.open()?;
+        let d = Download { owner: client.to_string(), value, offset: 0, touched: Instant::now() };
+        m.downloads.insert(sid, d);
+        Ok(sid)
+    }
+
+    /// Next chunk of at most `max_len` bytes (at least 1, so every call makes
+    /// progress). The session closes itself once the last byte has been
+    /// handed out.
+    pub fn next_chunk(&self, client: &str, sid: u64, max_len: usize) -> Result<Vec<u8>, KvzError> {
+        let (data, start, end) = {
+            let mut m = self.lock()?;
+            let d = m.download(sid, client)?;
+            let start = d.offset;
+            let end = start + max_len.max(1).min(d.value.data.len() - start);
+            d.offset = end;
+            d.touched = Instant::now();
+            let data = Arc::clone(&d.value.data);
+            if end == data.len() {
+                m.downloads.remove(&sid);
+            }
+            (data, start, end)
+        };
+        Ok(data[start..end].to_vec())
+    }
+
+    pub fn end_download(&self, client: &str, sid: u64) -> Result<(), KvzError> {
+        let mut m = self.lock()?;
+        m.download(sid, client)?;
+        m.downloads.remove(&sid);
+        Ok(())
+    }
+}
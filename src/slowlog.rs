@@ -0,0 +1,149 @@
+//! Slow request log.
+//!
+//! Requests whose handling time (receive to reply sent, including any wait
+//! for shard locks) reaches `--slowlog-threshold-us` are kept in a ring of
+//! the last `--slowlog-len` entries, read back with `SLOWLOG` (`kvz slowlog`).
+//! Keys are stored truncated to [`crate::server::MAX_SUMMARY_KEY`].
+
+use crate::metrics::COMMANDS;
+use crate::server::Summary;
+use clap::Args;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Frames per entry in a `SLOWLOG` reply.
+pub const ENTRY_FRAMES: usize = 10;
+/// Default `--slowlog-threshold-us`.
+const DEFAULT_THRESHOLD_US: u64 = 10_000;
+/// Default `--slowlog-len`.
+const DEFAULT_LEN: usize = 128;
+
+#[derive(Clone)]
+pub struct SlowEntry {
+    pub id: u64,
+    /// Unix time in milliseconds when the request finished.
+    pub time_ms: u64,
+    pub duration: Duration,
+    pub lock_wait: Duration,
+    pub worker: usize,
+    pub command: &'static str,
+    /// Empty for the default namespace.
+    pub namespace: String,
+    pub key: Vec<u8>,
+    /// Value bytes in the request plus payload bytes in the reply.
+    pub value_size: usize,
+    pub client: String,
+}
+
+impl SlowEntry {
+    /// The entry as `SLOWLOG` reply frames: id, time_ms, duration_us,
+    /// lock_wait_us, worker, value_size (8B BE each), command, namespace,
+    /// key, client.
+    pub fn frames(&self) -> [Vec<u8>; ENTRY_FRAMES] {
+        let n = |v: u64| v.to_be_bytes().to_vec();
+        [
+            n(self.id),
+            n(self.time_ms),
+            n(self.duration.as_micros() as u64),
+            n(self.lock_wait.as_micros() as u64),
+            n(self.worker as u64),
+            n(self.value_size as u64),
+            self.command.as_bytes().to_vec(),
+            self.namespace.as_bytes().to_vec(),
+            self.key.clone(),
+            self.client.as_bytes().to_vec(),
+        ]
+    }
+}
+
+pub struct SlowLog {
+    threshold: Duration,
+    capacity: usize,
+    next_id: AtomicU64,
+    entries: Mutex<VecDeque<SlowEntry>>,
+}
+
+impl Default for SlowLog {
+    fn default() -> Self {
+        SlowLogArgs::default().slowlog()
+    }
+}
+
+impl SlowLog {
+    pub fn new(threshold: Duration, capacity: usize) -> SlowLog {
+        SlowLog { threshold, capacity, next_id: AtomicU64::new(1), entries: Mutex::new(VecDeque::new()) }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Whether a request that took `duration` belongs in the log.
+    pub fn is_slow(&self, duration: Duration) -> bool {
+        self.enabled() && duration >= self.threshold
+    }
+
+    /// Log the request if it took at least the threshold.
+    pub fn record(&self, req: Summary, duration: Duration, lock_wait: Duration, worker: usize, reply_bytes: usize) {
+        if !self.is_slow(duration) {
+            return;
+        }
+        let time_ms = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+        let entry = SlowEntry {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            time_ms,
+            duration,
+            lock_wait,
+            worker,
+            command: COMMANDS[req.command],
+            namespace: req.namespace,
+            key: req.key,
+            value_size: req.value_size + reply_bytes,
+            client: req.client,
+        };
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Up to `count` entries, newest first.
+    pub fn latest(&self, count: usize) -> Vec<SlowEntry> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.iter().rev().take(count).cloned().collect()
+    }
+
+    /// Empty the log; returns how many entries were dropped.
+    pub fn reset(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let n = entries.len();
+        entries.clear();
+        n
+    }
+}
+
+/// `--slowlog-threshold-us`, `--slowlog-len`.
+#[derive(Args, Debug, Clone)]
+pub struct SlowLogArgs {
+    /// Log requests taking at least this many microseconds to handle
+    #[arg(long, value_name = "US", default_value_t = DEFAULT_THRESHOLD_US)]
+    pub slowlog_threshold_us: u64,
+    /// Slow log entries to keep (0 disables the slow log)
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_LEN)]
+    pub slowlog_len: usize,
+}
+
+impl Default for SlowLogArgs {
+    fn default() -> Self {
+        SlowLogArgs { slowlog_threshold_us: DEFAULT_THRESHOLD_US, slowlog_len: DEFAULT_LEN }
+    }
+}
+
+impl SlowLogArgs {
+    pub fn slowlog(&self) -> SlowLog {
+        SlowLog::new(Duration::from_micros(self.slowlog_threshold_us), self.slowlog_len)
+    }
+}
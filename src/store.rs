@@ -0,0 +1,220 @@
+//! In-memory sharded store used by both servers.
+
+use crate::payload::Payload;
+use anyhow::{anyhow, Result};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, LockResult, RwLock, TryLockError, TryLockResult};
+use std::time::{Duration, Instant};
+
+/// Stored value. The payload is shared, so cloning a `Value` (GET, chunked
+/// downloads) never copies the bytes under the shard lock.
+#[derive(Clone)]
+pub struct Value {
+    pub ts: u64,
+    pub data: Arc<Payload>,
+}
+
+/// Very small (fast) hasher for sharding (XOR/shift)
+#[derive(Default)]
+pub struct FastHasher(u64);
+impl Hasher for FastHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        // quick & dirty: xor-fold bytes
+        let mut h = self.0;
+        for &b in bytes {
+            h ^= b as u64;
+            h = h.rotate_left(5).wrapping_mul(0x9E3779B185EBCA87);
+        }
+        self.0 = h;
+    }
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+pub type FastBuild = BuildHasherDefault<FastHasher>;
+
+/// Rough per-entry bookkeeping overhead (map slot, key/value headers), added
+/// to key and value length when accounting memory.
+pub const ENTRY_OVERHEAD: usize = 64;
+
+fn entry_size(key: &[u8], data: &[u8]) -> usize {
+    key.len() + data.len() + ENTRY_OVERHEAD
+}
+
+thread_local! {
+    /// Time the current thread spent blocked on shard locks.
+    static LOCK_WAIT: Cell<Duration> = const { Cell::new(Duration::ZERO) };
+}
+
+/// Return and reset the current thread's accumulated shard lock wait. Each
+/// worker handles one request at a time, so this is the wait of its request.
+pub fn take_lock_wait() -> Duration {
+    LOCK_WAIT.replace(Duration::ZERO)
+}
+
+/// Take a shard lock, timing it only if it is contended.
+fn lock<G>(try_lock: impl FnOnce() -> TryLockResult<G>, lock: impl FnOnce() -> LockResult<G>) -> Result<G> {
+    match try_lock() {
+        Ok(guard) => return Ok(guard),
+        Err(TryLockError::Poisoned(_)) => return Err(anyhow!("store poisoned")),
+        Err(TryLockError::WouldBlock) => {}
+    }
+    let start = Instant::now();
+    let guard = lock().map_err(|_| anyhow!("store poisoned"));
+    LOCK_WAIT.set(LOCK_WAIT.get() + start.elapsed());
+    guard
+}
+
+#[derive(Default)]
+struct Shard {
+    map: HashMap<Vec<u8>, Value, FastBuild>,
+    /// Sum of `entry_size` over `map`.
+    bytes: usize,
+}
+
+/// Outcome of [`ShardedStore::put`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Put {
+    /// Stored; `delta` is the change in accounted bytes.
+    Stored { delta: isize },
+    Stale,
+    /// Refused by the `admit` check.
+    OverQuota,
+    /// The store was [closed](ShardedStore::close).
+    Closed,
+}
+
+/// Sharded store: Vec<RwLock<HashMap>>
+pub struct ShardedStore {
+    shards: Vec<RwLock<Shard>>,
+    mask: usize, // if power-of-two sized, we can mask. Otherwise use modulo.
+    pow2: bool,
+    // Totals across shards, kept so quota checks need not lock every shard.
+    keys: AtomicUsize,
+    bytes: AtomicUsize,
+    /// Set before a dropped namespace is cleared; refuses further puts.
+    closed: AtomicBool,
+}
+
+impl ShardedStore {
+    pub fn new(n: usize) -> Self {
+        let cap = n.next_power_of_two();
+        let pow2 = cap == n;
+        let mask = if pow2 { n - 1 } else { 0 };
+        let mut shards = Vec::with_capacity(n);
+        for _ in 0..n {
+            shards.push(RwLock::new(Shard::default()));
+        }
+        Self {
+            shards,
+            mask,
+            pow2,
+            keys: AtomicUsize::new(0),
+            bytes: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    #[inline]
+    fn shard_index(&self, key: &[u8]) -> usize {
+        let mut h = FastHasher(0);
+        h.write(key);
+        let v = h.finish() as usize;
+        if self.pow2 {
+            v & self.mask
+        } else {
+            v % self.shards.len()
+        }
+    }
+
+    /// PUT semantics: replace only if new_ts >= old_ts. A write that would grow
+    /// the accounted size is first offered to `admit` (with the key and the
+    /// growth in bytes, under the shard lock) and refused if it returns false; quotas
+    /// built on this are approximate, as other shards keep writing meanwhile.
+    /// Err if the shard lock is poisoned.
+    pub fn put(&self, key: Vec<u8>, ts: u64, data: Payload, admit: impl FnOnce(&[u8], usize) -> bool) -> Result<Put> {
+        let s = &self.shards[self.shard_index(&key)];
+        let mut shard = lock(|| s.try_write(), || s.write())?;
+        // Checked under the shard lock: a put that gets the lock before a
+        // following clear() is removed by it, any later one is refused here.
+        if self.closed.load(Ordering::Acquire) {
+            return Ok(Put::Closed);
+        }
+        let new_size = entry_size(&key, &data);
+        let old_size = match shard.map.get(&key) {
+            Some(v) if ts < v.ts => return Ok(Put::Stale),
+            Some(v) => Some(entry_size(&key, &v.data)),
+            None => None,
+        };
+        let growth = new_size.saturating_sub(old_size.unwrap_or(0));
+        if growth > 0 && !admit(&key, growth) {
+            return Ok(Put::OverQuota);
+        }
+        shard.map.insert(key, Value { ts, data: Arc::new(data) });
+        shard.bytes = shard.bytes + new_size - old_size.unwrap_or(0);
+        self.bytes.fetch_add(new_size, Ordering::Relaxed);
+        match old_size {
+            Some(old) => self.bytes.fetch_sub(old, Ordering::Relaxed),
+            None => self.keys.fetch_add(1, Ordering::Relaxed),
+        };
+        Ok(Put::Stored { delta: new_size as isize - old_size.unwrap_or(0) as isize })
+    }
+
+    /// GET: None if miss.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Value>> {
+        let s = &self.shards[self.shard_index(key)];
+        let shard = lock(|| s.try_read(), || s.read())?;
+        Ok(shard.map.get(key).cloned())
+    }
+
+    /// Refuse all further puts, so a [`clear`](Self::clear) that follows
+    /// leaves the store empty for good.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+    }
+
+    /// Remove every key, calling `removed` with each key and its accounted
+    /// size; returns how many were removed.
+    pub fn clear(&self, mut removed: impl FnMut(&[u8], usize)) -> Result<usize> {
+        let mut count = 0;
+        for s in &self.shards {
+            let mut shard = lock(|| s.try_write(), || s.write())?;
+            let map = std::mem::take(&mut shard.map);
+            for (key, v) in &map {
+                removed(key, entry_size(key, &v.data));
+            }
+            count += map.len();
+            self.keys.fetch_sub(map.len(), Ordering::Relaxed);
+            self.bytes.fetch_sub(shard.bytes, Ordering::Relaxed);
+            shard.bytes = 0;
+        }
+        Ok(count)
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Estimated memory held by keys and values, see [`ENTRY_OVERHEAD`].
+    pub fn bytes(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// `(keys, bytes)` for each shard.
+    pub fn shard_stats(&self) -> Result<Vec<(usize, usize)>> {
+        self.shards
+            .iter()
+            .map(|s| {
+                let shard = lock(|| s.try_read(), || s.read())?;
+                Ok((shard.map.len(), shard.bytes))
+            })
+            .collect()
+    }
+}